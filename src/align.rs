@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use errors::*;
+use stitch::FillPattern;
+
+/// Flash erase or page size offsets and lengths have to be aligned to
+#[derive(Debug, Clone, Copy)]
+pub struct Alignment {
+    pub sector: u64,
+    /// pad lengths up to the next sector boundary instead of rejecting them
+    pub pad: Option<FillPattern>,
+}
+
+impl Alignment {
+    pub fn new(sector: u64, pad: Option<FillPattern>) -> Result<Self> {
+        if sector == 0 {
+            return Err(ScalpelError::ArgumentError
+                .context("Alignment must not be 0")
+                .into());
+        }
+        Ok(Self { sector, pad })
+    }
+
+    /// reject `value` if it is not a multiple of the sector size,
+    /// offsets can not be fixed by padding
+    pub fn check(&self, binary: &Path, what: &str, value: u64) -> Result<()> {
+        if value.is_multiple_of(self.sector) {
+            Ok(())
+        } else {
+            Err(ScalpelError::AlignmentError {
+                binary: format!("{:?}", binary),
+                what: what.to_string(),
+                value,
+                sector: self.sector,
+            }
+            .into())
+        }
+    }
+
+    /// the length `len` of `what` within `binary` has after padding, rejects it if padding is not allowed
+    pub fn length(&self, binary: &Path, what: &str, len: u64) -> Result<u64> {
+        if self.pad.is_none() {
+            self.check(binary, what, len)?;
+        }
+        len.checked_next_multiple_of(self.sector).ok_or_else(|| {
+            ScalpelError::ArgumentError
+                .context(format!("Padding length {} of {:?} overflows", len, binary))
+                .into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reject_or_pad() {
+        let binary = Path::new("app.bin");
+        let strict = Alignment::new(0x1000, None).expect("Failed to create alignment");
+        assert!(strict.check(binary, "offset", 0x2000).is_ok());
+        assert_eq!(strict.length(binary, "length", 0x2000).unwrap(), 0x2000);
+        match strict.check(binary, "offset", 0x2001) {
+            Err(e) => assert_eq!(
+                format!("{}", e),
+                "offset 0x2001 of \"app.bin\" is not aligned to 0x1000"
+            ),
+            Ok(_) => panic!("Accepted an unaligned offset"),
+        }
+        match strict.length(binary, "length of section 0x10..0x2011", 0x2001) {
+            Err(e) => assert_eq!(
+                format!("{}", e),
+                "length of section 0x10..0x2011 0x2001 of \"app.bin\" is not aligned to 0x1000"
+            ),
+            Ok(_) => panic!("Accepted an unaligned length"),
+        }
+
+        let padding = Alignment::new(0x1000, Some(FillPattern::One)).expect("Failed to create alignment");
+        assert!(padding.check(binary, "start", 0x10).is_err());
+        assert_eq!(padding.length(binary, "length", 0x2001).unwrap(), 0x3000);
+        assert_eq!(padding.length(binary, "length", 0).unwrap(), 0);
+
+        assert!(Alignment::new(0, None).is_err());
+    }
+}
@@ -0,0 +1,372 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes192, Aes256};
+use bytes::Bytes;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::path::Path;
+
+use errors::*;
+use signer::{Signer, Verifier};
+use stitch::{read_file, write_file};
+
+const BLOCK_LEN: usize = 16;
+
+/// Unauthenticated AES modes as supported by MCU ROM bootloaders
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// counter mode with the IV as initial 128 bit big endian counter
+    Ctr,
+    Cbc,
+}
+
+/// How the encrypted section is aligned to the AES block size
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Padding {
+    /// the section has to be aligned already for CBC
+    #[default]
+    None,
+    /// 1 to 16 bytes of the padding length, removed again by decryption
+    Pkcs7,
+    /// zeros up to the next block, kept by decryption
+    Zero,
+}
+
+impl Padding {
+    fn pad(self, section: &mut Vec<u8>) {
+        let pad = BLOCK_LEN - section.len() % BLOCK_LEN;
+        match self {
+            Padding::None => {}
+            Padding::Pkcs7 => section.extend(vec![pad as u8; pad]),
+            Padding::Zero if pad < BLOCK_LEN => section.extend(vec![0; pad]),
+            Padding::Zero => {}
+        }
+    }
+
+    fn unpad(self, section: &mut Vec<u8>) -> Result<()> {
+        if self == Padding::Pkcs7 {
+            let pad = section.last().cloned().unwrap_or(0) as usize;
+            if pad == 0 || pad > BLOCK_LEN || pad > section.len() || section[section.len() - pad..].iter().any(|byte| *byte as usize != pad) {
+                return Err(ScalpelError::EncryptionError {
+                    r: "invalid PKCS#7 padding, wrong key or range".to_string(),
+                }
+                .into());
+            }
+            let len = section.len() - pad;
+            section.truncate(len);
+        }
+        Ok(())
+    }
+}
+
+/// AES with a key of 16, 24 or 32 bytes
+enum Aes {
+    Aes128(Aes128),
+    Aes192(Aes192),
+    Aes256(Aes256),
+}
+
+impl Aes {
+    fn new(key: &[u8]) -> Result<Self> {
+        Ok(match key.len() {
+            16 => Aes::Aes128(Aes128::new(GenericArray::from_slice(key))),
+            24 => Aes::Aes192(Aes192::new(GenericArray::from_slice(key))),
+            32 => Aes::Aes256(Aes256::new(GenericArray::from_slice(key))),
+            len => {
+                return Err(ScalpelError::KeyError {
+                    r: format!("key has {} bytes, AES needs a key of 16, 24 or 32", len),
+                }
+                .into())
+            }
+        })
+    }
+
+    fn encrypt_block(&self, block: &mut [u8]) {
+        let block = GenericArray::from_mut_slice(block);
+        match self {
+            Aes::Aes128(aes) => aes.encrypt_block(block),
+            Aes::Aes192(aes) => aes.encrypt_block(block),
+            Aes::Aes256(aes) => aes.encrypt_block(block),
+        }
+    }
+
+    fn decrypt_block(&self, block: &mut [u8]) {
+        let block = GenericArray::from_mut_slice(block);
+        match self {
+            Aes::Aes128(aes) => aes.decrypt_block(block),
+            Aes::Aes192(aes) => aes.decrypt_block(block),
+            Aes::Aes256(aes) => aes.decrypt_block(block),
+        }
+    }
+}
+
+impl Mode {
+    fn check(self, data: &[u8]) -> Result<()> {
+        if self == Mode::Cbc && !data.len().is_multiple_of(BLOCK_LEN) {
+            return Err(ScalpelError::ArgumentError
+                .context(format!("CBC needs a multiple of {} bytes, not {}, use padding", BLOCK_LEN, data.len()))
+                .into());
+        }
+        Ok(())
+    }
+
+    /// the key stream of CTR, which en- and decrypts
+    fn apply_keystream(aes: &Aes, iv: &[u8; BLOCK_LEN], data: &mut [u8]) {
+        let mut counter = u128::from_be_bytes(*iv);
+        for chunk in data.chunks_mut(BLOCK_LEN) {
+            let mut keystream = counter.to_be_bytes();
+            aes.encrypt_block(&mut keystream);
+            for (byte, key) in chunk.iter_mut().zip(keystream.iter()) {
+                *byte ^= key;
+            }
+            counter = counter.wrapping_add(1);
+        }
+    }
+
+    fn encrypt(self, aes: &Aes, iv: &[u8; BLOCK_LEN], data: &mut [u8]) -> Result<()> {
+        self.check(data)?;
+        match self {
+            Mode::Ctr => Self::apply_keystream(aes, iv, data),
+            Mode::Cbc => {
+                let mut previous = *iv;
+                for block in data.chunks_mut(BLOCK_LEN) {
+                    for (byte, previous) in block.iter_mut().zip(previous.iter()) {
+                        *byte ^= previous;
+                    }
+                    aes.encrypt_block(block);
+                    previous.copy_from_slice(block);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn decrypt(self, aes: &Aes, iv: &[u8; BLOCK_LEN], data: &mut [u8]) -> Result<()> {
+        self.check(data)?;
+        match self {
+            Mode::Ctr => Self::apply_keystream(aes, iv, data),
+            Mode::Cbc => {
+                let mut previous = *iv;
+                for block in data.chunks_mut(BLOCK_LEN) {
+                    let mut ciphertext = [0u8; BLOCK_LEN];
+                    ciphertext.copy_from_slice(block);
+                    aes.decrypt_block(block);
+                    for (byte, previous) in block.iter_mut().zip(previous.iter()) {
+                        *byte ^= previous;
+                    }
+                    previous = ciphertext;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How and which section of an image is encrypted and where its IV is stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub mode: Mode,
+    pub padding: Padding,
+    pub start: u64,
+    pub end: u64,
+    pub iv_offset: u64,
+}
+
+impl Layout {
+    /// check that the range and the IV slot fit into `len` bytes without overlapping
+    fn check(&self, len: u64) -> Result<()> {
+        let iv_end = self.iv_slot()?.end as u64;
+        if self.start > self.end || self.end > len || iv_end > len || (self.iv_offset < self.end && iv_end > self.start) {
+            return Err(ScalpelError::ArgumentError
+                .context(format!(
+                    "Range {}..{} and IV slot {}..{} have to be within the {} bytes of the input without overlapping",
+                    self.start, self.end, self.iv_offset, iv_end, len
+                ))
+                .into());
+        }
+        Ok(())
+    }
+
+    fn section(&self) -> ::std::ops::Range<usize> {
+        self.start as usize..self.end as usize
+    }
+
+    fn iv_slot(&self) -> Result<::std::ops::Range<usize>> {
+        let start = self.iv_offset as usize;
+        start
+            .checked_add(BLOCK_LEN)
+            .map(|end| start..end)
+            .ok_or_else(|| {
+                ScalpelError::ArgumentError
+                    .context(format!("IV slot at {:#X} exceeds the address space", self.iv_offset))
+                    .into()
+            })
+    }
+}
+
+/// encrypt the section of `bytes` with `iv`, which is stored in the IV slot in front of or behind the section
+///
+/// The padded section replaces the range, so the image grows by the padding and an IV slot behind the section moves along.
+pub fn encrypt(key: &[u8], layout: &Layout, iv: [u8; BLOCK_LEN], mut bytes: Vec<u8>) -> Result<Vec<u8>> {
+    layout.check(bytes.len() as u64)?;
+    let aes = Aes::new(key)?;
+    bytes[layout.iv_slot()?].copy_from_slice(&iv);
+
+    let mut section = bytes[layout.section()].to_vec();
+    layout.padding.pad(&mut section);
+    layout.mode.encrypt(&aes, &iv, &mut section)?;
+    bytes.splice(layout.section(), section);
+    Ok(bytes)
+}
+
+/// decrypt the section of `bytes` with the IV of the IV slot, PKCS#7 padding is removed
+pub fn decrypt(key: &[u8], layout: &Layout, mut bytes: Vec<u8>) -> Result<Vec<u8>> {
+    layout.check(bytes.len() as u64)?;
+    let aes = Aes::new(key)?;
+    let mut iv = [0u8; BLOCK_LEN];
+    iv.copy_from_slice(&bytes[layout.iv_slot()?]);
+
+    let mut section = bytes[layout.section()].to_vec();
+    layout.mode.decrypt(&aes, &iv, &mut section)?;
+    layout.padding.unpad(&mut section)?;
+    bytes.splice(layout.section(), section);
+    Ok(bytes)
+}
+
+/// encrypt the file at `path` with a random IV and the raw key at `key` and write it to `output`,
+/// the signature of `signer` over the encrypted image is appended
+pub fn encrypt_file(key: &Path, layout: &Layout, path: &Path, output: &Path, signer: Option<&Signer>) -> Result<()> {
+    let mut iv = [0u8; BLOCK_LEN];
+    SystemRandom::new()
+        .fill(&mut iv)
+        .map_err(|_| ScalpelError::EncryptionError { r: "no random IV".to_string() })?;
+    let mut bytes = encrypt(&read_file(key)?, layout, iv, read_file(path)?.to_vec())?;
+    if let Some(signer) = signer {
+        let signature = signer.calculate_signature(&Bytes::from(&bytes[..]))?;
+        bytes.extend_from_slice(&signature);
+    }
+    write_file(output, &bytes)
+}
+
+/// decrypt the file at `path` with the raw key at `key` and write it to `output`,
+/// the signature appended by `encrypt_file` is verified and removed first
+pub fn decrypt_file(key: &Path, layout: &Layout, path: &Path, output: &Path, verifier: Option<&Verifier>) -> Result<()> {
+    let mut bytes = read_file(path)?.to_vec();
+    if let Some(verifier) = verifier {
+        let len = bytes.len().checked_sub(verifier.signature_len()).ok_or(ScalpelError::ContentError)?;
+        verifier.verify(&bytes[..len], &bytes[len..])?;
+        bytes.truncate(len);
+    }
+    let bytes = decrypt(&read_file(key)?, layout, bytes)?;
+    write_file(output, &bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use format::decode_hex;
+    use testing::TempFile;
+
+    // NIST SP 800-38A, appendix F
+    const PLAINTEXT: &str = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710";
+    const KEY_128: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+    const KEY_256: &str = "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4";
+
+    fn hex(digits: &str) -> Vec<u8> {
+        decode_hex(0, digits).expect("Invalid hex")
+    }
+
+    fn layout(mode: Mode, padding: Padding, start: u64, end: u64, iv_offset: u64) -> Layout {
+        Layout { mode, padding, start, end, iv_offset }
+    }
+
+    fn iv(digits: &str) -> [u8; BLOCK_LEN] {
+        let mut iv = [0u8; BLOCK_LEN];
+        iv.copy_from_slice(&hex(digits));
+        iv
+    }
+
+    #[test]
+    fn known_answer() {
+        let cbc_iv = "000102030405060708090a0b0c0d0e0f";
+        let ctr_iv = "f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff";
+        let vectors = [
+            (KEY_128, Mode::Cbc, cbc_iv, "7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b273bed6b8e3c1743b7116e69e222295163ff1caa1681fac09120eca307586e1a7"),
+            (KEY_256, Mode::Cbc, cbc_iv, "f58c4c04d6e5f1ba779eabfb5f7bfbd69cfc4e967edb808d679f777bc6702c7d39f23369a9d9bacfa530e26304231461b2eb05e2c39be9fcda6c19078c6a9d1b"),
+            (KEY_128, Mode::Ctr, ctr_iv, "874d6191b620e3261bef6864990db6ce9806f66b7970fdff8617187bb9fffdff5ae4df3edbd5d35e5b4f09020db03eab1e031dda2fbe03d1792170a0f3009cee"),
+            (KEY_256, Mode::Ctr, ctr_iv, "601ec313775789a5b7a7f504bbf3d228f443e3ca4d62b59aca84e990cacaf5c52b0930daa23de94ce87017ba2d84988ddfc9c58db67aada613c2dd08457941a6"),
+        ];
+        for (key, mode, iv_digits, ciphertext) in vectors.iter() {
+            // the IV is stored in a header in front of the encrypted range
+            let mut image = vec![0u8; 16];
+            image.extend(hex(PLAINTEXT));
+            let encrypted = encrypt(&hex(key), &layout(*mode, Padding::None, 16, 80, 0), iv(iv_digits), image.clone()).expect("Failed to encrypt");
+            assert_eq!(encrypted[..16], iv(iv_digits)[..]);
+            assert_eq!(encrypted[16..], hex(ciphertext)[..], "{:?}", mode);
+            assert_eq!(decrypt(&hex(key), &layout(*mode, Padding::None, 16, 80, 0), encrypted).expect("Failed to decrypt")[16..], image[16..]);
+        }
+
+        // CTR needs no full blocks
+        let encrypted = encrypt(&hex(KEY_128), &layout(Mode::Ctr, Padding::None, 0, 20, 48), iv(ctr_iv), hex(PLAINTEXT)).expect("Failed to encrypt");
+        assert_eq!(encrypted[..20], hex("874d6191b620e3261bef6864990db6ce9806f66b")[..]);
+        assert!(encrypt(&hex(KEY_128), &layout(Mode::Cbc, Padding::None, 0, 20, 48), iv(cbc_iv), hex(PLAINTEXT)).is_err());
+        assert!(encrypt(&hex(KEY_128)[1..], &layout(Mode::Cbc, Padding::None, 0, 32, 48), iv(cbc_iv), hex(PLAINTEXT)).is_err());
+    }
+
+    #[test]
+    fn padding() {
+        let key = hex(KEY_256);
+        let image: Vec<u8> = (0..100).collect();
+        let iv = iv("000102030405060708090a0b0c0d0e0f");
+        // the IV slot must not overlap the range
+        assert!(encrypt(&key, &layout(Mode::Cbc, Padding::Pkcs7, 16, 100, 8), iv, image.clone()).is_err());
+        assert!(encrypt(&key, &layout(Mode::Cbc, Padding::Pkcs7, 16, 100, 90), iv, image.clone()).is_err());
+        assert!(encrypt(&key, &layout(Mode::Cbc, Padding::Pkcs7, 16, 100, u64::MAX - 8), iv, image.clone()).is_err());
+        assert!(decrypt(&key, &layout(Mode::Cbc, Padding::Pkcs7, 16, 100, u64::MAX), image.clone()).is_err());
+
+        // 84 bytes get 12 bytes of padding
+        let encrypted = encrypt(&key, &layout(Mode::Cbc, Padding::Pkcs7, 16, 100, 0), iv, image.clone()).expect("Failed to encrypt");
+        assert_eq!(encrypted.len(), 112);
+        assert_eq!(decrypt(&key, &layout(Mode::Cbc, Padding::Pkcs7, 16, 112, 0), encrypted.clone()).expect("Failed to decrypt")[16..], image[16..]);
+        assert!(decrypt(&hex(KEY_128), &layout(Mode::Cbc, Padding::Pkcs7, 16, 112, 0), encrypted).is_err());
+        // aligned sections get a whole block
+        let encrypted = encrypt(&key, &layout(Mode::Cbc, Padding::Pkcs7, 16, 96, 0), iv, image.clone()).expect("Failed to encrypt");
+        assert_eq!(encrypted.len(), 116);
+
+        // zeros are kept
+        let encrypted = encrypt(&key, &layout(Mode::Ctr, Padding::Zero, 16, 100, 0), iv, image.clone()).expect("Failed to encrypt");
+        assert_eq!(encrypted.len(), 112);
+        let decrypted = decrypt(&key, &layout(Mode::Ctr, Padding::Zero, 16, 112, 0), encrypted).expect("Failed to decrypt");
+        assert_eq!(decrypted[16..100], image[16..]);
+        assert_eq!(decrypted[100..], [0; 12]);
+        let encrypted = encrypt(&key, &layout(Mode::Cbc, Padding::Zero, 20, 100, 0), iv, image.clone()).expect("Failed to encrypt");
+        assert_eq!(encrypted.len(), 100);
+    }
+
+    #[test]
+    fn encrypt_then_sign() {
+        let image = TempFile::copy("tmp/test_bytes", "tmp/test_encrypt_cbc.bin");
+        let path = image.path();
+        let decrypted = TempFile::new("tmp/test_encrypt_cbc-decrypted.bin");
+        let output = decrypted.path();
+        let key_file = TempFile::new("tmp/test_encrypt_cbc.key");
+        let key = key_file.path();
+        write_file(key, &hex(KEY_128)).expect("Failed to write key");
+        let signer = Signer::from_pkcs8_file(Path::new("tmp/rsa2048_keypair.pk8")).expect("Failed to read keys");
+        let verifier = signer.verifier().expect("Failed to get verifier");
+
+        encrypt_file(key, &layout(Mode::Cbc, Padding::Pkcs7, 0x100, 2048, 0x10), path, path, Some(&signer)).expect("Failed to encrypt");
+        let encrypted = read_file(path).expect("Failed to read file");
+        assert_eq!(encrypted.len(), 2048 + 16 + 256);
+        assert!(verifier.verify_file(path).is_ok());
+
+        decrypt_file(key, &layout(Mode::Cbc, Padding::Pkcs7, 0x100, 2064, 0x10), path, output, Some(&verifier)).expect("Failed to decrypt");
+        let plain = read_file(output).expect("Failed to read file");
+        let original = read_file(Path::new("tmp/test_bytes")).expect("Failed to read file");
+        assert_eq!(plain[0x100..], original[0x100..]);
+        assert_eq!(plain[0x10..0x20], encrypted[0x10..0x20]);
+
+        // the signature covers the encrypted image
+        let signer = Signer::from_pkcs8_file(Path::new("tmp/p256_keypair.pk8")).expect("Failed to read keys");
+        assert!(decrypt_file(key, &layout(Mode::Cbc, Padding::Pkcs7, 0x100, 2064, 0x10), path, output, Some(&signer.verifier().unwrap())).is_err());
+    }
+}
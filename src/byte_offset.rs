@@ -1,11 +1,12 @@
 use errors::*;
 use std::fmt;
 use serde::de;
-use regex::{Regex,Captures};
+use elf::Elf;
 
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, Copy)]
 pub enum Magnitude {
+    #[default]
     Unit,
     K,
     Ki,
@@ -13,12 +14,12 @@ pub enum Magnitude {
     Mi,
     G,
     Gi,
-}
-
-impl Default for Magnitude {
-    fn default() -> Self {
-        Magnitude::Unit
-    }
+    T,
+    Ti,
+    P,
+    Pi,
+    E,
+    Ei,
 }
 
 impl Magnitude {
@@ -31,6 +32,12 @@ impl Magnitude {
             "Mi" => Ok(Magnitude::Mi),
             "G" => Ok(Magnitude::G),
             "Gi" => Ok(Magnitude::Gi),
+            "T" => Ok(Magnitude::T),
+            "Ti" => Ok(Magnitude::Ti),
+            "P" => Ok(Magnitude::P),
+            "Pi" => Ok(Magnitude::Pi),
+            "E" => Ok(Magnitude::E),
+            "Ei" => Ok(Magnitude::Ei),
             _ => {
                 debug!("No idea what to do with {} as magnitude ", mag_str);
                 Err(ScalpelError::ParsingError{r: format!("Unknown Magnitude {}", mag_str)}.into())
@@ -38,39 +45,406 @@ impl Magnitude {
         }
     }
 
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Magnitude::Unit => "",
+            Magnitude::K => "K",
+            Magnitude::Ki => "Ki",
+            Magnitude::M => "M",
+            Magnitude::Mi => "Mi",
+            Magnitude::G => "G",
+            Magnitude::Gi => "Gi",
+            Magnitude::T => "T",
+            Magnitude::Ti => "Ti",
+            Magnitude::P => "P",
+            Magnitude::Pi => "Pi",
+            Magnitude::E => "E",
+            Magnitude::Ei => "Ei",
+        }
+    }
+
     pub fn as_u64(&self) -> u64 {
         match self {
             Magnitude::Unit => 1u64,
             Magnitude::K => 1000u64,
             Magnitude::Ki => 1024u64,
-            Magnitude::M => 1000u64*1000u64,
-            Magnitude::Mi => 1024u64*1024u64,
-            Magnitude::G => 1000u64*1000u64*1000u64,
-            Magnitude::Gi => 1024u64*1024u64*1024u64,
+            Magnitude::M => 1000u64.pow(2),
+            Magnitude::Mi => 1024u64.pow(2),
+            Magnitude::G => 1000u64.pow(3),
+            Magnitude::Gi => 1024u64.pow(3),
+            Magnitude::T => 1000u64.pow(4),
+            Magnitude::Ti => 1024u64.pow(4),
+            Magnitude::P => 1000u64.pow(5),
+            Magnitude::Pi => 1024u64.pow(5),
+            Magnitude::E => 1000u64.pow(6),
+            Magnitude::Ei => 1024u64.pow(6),
         }
     }
 
-    pub fn as_usize(&self) -> usize {
-        self.as_u64() as usize
+    /// `num` in units of this magnitude, errors if it does not fit into 64 bits
+    pub fn scale(&self, num: u64) -> Result<u64> {
+        num.checked_mul(self.as_u64()).ok_or_else(|| {
+            ScalpelError::ArgumentError
+                .context(format!("{}{} does not fit into 64 bits", num, self.suffix()))
+                .into()
+        })
     }
 }
 
+/// Cursor over the textual representation of an offset, so errors can point at a character
+struct Scanner<'a> {
+    input: &'a str,
+    /// byte position of the next character
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    /// consume `c` if it is the next character apart from whitespace
+    fn accept(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.accept(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    /// `[-] <term> ((+|-) <term>)*`, a leading `-` counts backwards from `end`
+    fn expr(&mut self) -> Result<Expr> {
+        let mut expr = if self.accept('-') {
+            Expr::Sub(Box::new(Expr::End), Box::new(self.term()?))
+        } else {
+            self.term()?
+        };
+        loop {
+            if self.accept('+') {
+                expr = Expr::Add(Box::new(expr), Box::new(self.term()?));
+            } else if self.accept('-') {
+                expr = Expr::Sub(Box::new(expr), Box::new(self.term()?));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
 
-#[derive(Debug, Default)]
+    /// `<factor> ((*|/) <factor>)*`
+    fn term(&mut self) -> Result<Expr> {
+        let mut expr = self.factor()?;
+        loop {
+            if self.accept('*') {
+                expr = Expr::Mul(Box::new(expr), Box::new(self.factor()?));
+            } else if self.accept('/') {
+                expr = Expr::Div(Box::new(expr), Box::new(self.factor()?));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// a number, `end`, `sym:<name>`, `align(<expr>, <expr>)` or a parenthesized expression
+    fn factor(&mut self) -> Result<Expr> {
+        self.skip_whitespace();
+        if self.accept('(') {
+            let expr = self.expr()?;
+            self.expect(')')?;
+            return Ok(expr);
+        }
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() => {
+                let (num, magnitude) = self.number()?;
+                return Ok(Expr::Number(num, magnitude));
+            }
+            Some(c) if c.is_ascii_alphabetic() => {}
+            _ => return Err(self.error("expected a number, 'end', 'align(..)' or 'sym:<name>'")),
+        }
+
+        let start = self.pos;
+        let ident: String = self.input[start..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+        self.pos += ident.len();
+        match ident.as_str() {
+            "end" => Ok(Expr::End),
+            "align" => {
+                self.expect('(')?;
+                let value = self.expr()?;
+                self.expect(',')?;
+                let alignment = self.expr()?;
+                self.expect(')')?;
+                Ok(Expr::Align(Box::new(value), Box::new(alignment)))
+            }
+            "sym" if self.peek() == Some(':') => {
+                self.pos += 1;
+                let name: String = self.input[self.pos..]
+                    .chars()
+                    .take_while(|c| !c.is_whitespace() && !"+-*/(),".contains(*c))
+                    .collect();
+                if name.is_empty() {
+                    return Err(self.error("expected a symbol name"));
+                }
+                self.pos += name.len();
+                Ok(Expr::Symbol(name))
+            }
+            _ => {
+                self.pos = start;
+                Err(self.error("expected a number, 'end', 'align(..)' or 'sym:<name>'"))
+            }
+        }
+    }
+
+    /// parsing error which points at the current position
+    fn error(&self, what: &str) -> Error {
+        let found = match self.peek() {
+            Some(c) => format!("'{}'", c),
+            None => "end of input".to_string(),
+        };
+        ScalpelError::ParsingError {
+            r: format!(
+                "{}, found {} at position {} of \"{}\"",
+                what,
+                found,
+                self.pos + 1,
+                self.input
+            ),
+        }
+        .into()
+    }
+
+    /// `[0x|0o|0b]<digits>[<magnitude>]` where digits may be separated by `_`
+    fn number(&mut self) -> Result<(u64, Magnitude)> {
+        let rest = &self.input[self.pos..];
+        let (radix, name) = if rest.starts_with("0x") || rest.starts_with("0X") {
+            (16, "a hexadecimal")
+        } else if rest.starts_with("0o") || rest.starts_with("0O") {
+            (8, "an octal")
+        } else if rest.starts_with("0b") || rest.starts_with("0B") {
+            (2, "a binary")
+        } else {
+            (10, "a decimal")
+        };
+        if radix != 10 {
+            self.pos += 2;
+        }
+
+        let start = self.pos;
+        let mut num: Option<u64> = None;
+        while let Some(c) = self.peek() {
+            if c == '_' && num.is_some() {
+                self.pos += 1;
+                continue;
+            }
+            let digit = match c.to_digit(radix) {
+                Some(digit) => u64::from(digit),
+                None if c.is_ascii_digit() => {
+                    return Err(self.error(&format!("expected {} digit", name)))
+                }
+                None => break,
+            };
+            num = Some(
+                num.unwrap_or(0)
+                    .checked_mul(u64::from(radix))
+                    .and_then(|value| value.checked_add(digit))
+                    .ok_or_else(|| ScalpelError::ParsingError {
+                        r: format!(
+                            "number at position {} of \"{}\" does not fit into 64 bits",
+                            start + 1,
+                            self.input
+                        ),
+                    })?,
+            );
+            self.pos += 1;
+        }
+        let num = num.ok_or_else(|| self.error(&format!("expected {} digit", name)))?;
+
+        // longest magnitude suffix first, so `Ki` is not taken for `K`
+        let rest = &self.input[self.pos..];
+        let magnitude = match [2, 1]
+            .iter()
+            .filter_map(|&len| rest.get(..len))
+            .filter_map(|suffix| Magnitude::parse(suffix).ok().map(|m| (suffix.len(), m)))
+            .next()
+        {
+            Some((len, magnitude)) => {
+                self.pos += len;
+                magnitude
+            }
+            None if self.peek().is_some_and(|c| c.is_ascii_alphabetic()) => {
+                return Err(self.error(&format!(
+                    "expected {} digit or a magnitude such as K, Ki, M, Mi, G, Gi, T, Ti, P, Pi, E or Ei",
+                    name
+                )))
+            }
+            None => Magnitude::Unit,
+        };
+
+        Ok((num, magnitude))
+    }
+}
+
+/// Parsed offset expression, evaluated once the input is known
+#[derive(Debug)]
+enum Expr {
+    Number(u64, Magnitude),
+    /// length of the input
+    End,
+    /// file offset of an ELF symbol
+    Symbol(String),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    /// first multiple of the alignment which is not smaller than the value
+    Align(Box<Expr>, Box<Expr>),
+}
+
+/// Everything an expression may refer to
+struct Environment<'a> {
+    text: &'a str,
+    end: Option<u64>,
+    symbols: Option<&'a Elf>,
+}
+
+impl<'a> Environment<'a> {
+    fn error(&self, what: String) -> Error {
+        ScalpelError::ArgumentError
+            .context(format!("{} in offset \"{}\"", what, self.text))
+            .into()
+    }
+}
+
+impl Expr {
+    fn evaluate(&self, env: &Environment) -> Result<u64> {
+        let binary = |lhs: &Expr, rhs: &Expr| -> Result<(u64, u64)> {
+            Ok((lhs.evaluate(env)?, rhs.evaluate(env)?))
+        };
+        match self {
+            Expr::Number(num, magnitude) => magnitude
+                .scale(*num)
+                .map_err(|e| env.error(format!("{}", e))),
+            Expr::End => env
+                .end
+                .ok_or_else(|| env.error("'end' is unknown".to_string())),
+            Expr::Symbol(name) => {
+                let elf = env.symbols.ok_or_else(|| {
+                    ScalpelError::ArgumentError
+                        .context(format!("Offset relative to symbol {} requires --symbols", name))
+                })?;
+                elf.symbol_offset(name)
+            }
+            Expr::Add(lhs, rhs) => {
+                let (lhs, rhs) = binary(lhs, rhs)?;
+                lhs.checked_add(rhs)
+                    .ok_or_else(|| env.error(format!("{} + {} overflows", lhs, rhs)))
+            }
+            Expr::Sub(lhs, rhs) => {
+                let (lhs, rhs) = binary(lhs, rhs)?;
+                lhs.checked_sub(rhs)
+                    .ok_or_else(|| env.error(format!("{} - {} is negative", lhs, rhs)))
+            }
+            Expr::Mul(lhs, rhs) => {
+                let (lhs, rhs) = binary(lhs, rhs)?;
+                lhs.checked_mul(rhs)
+                    .ok_or_else(|| env.error(format!("{} * {} overflows", lhs, rhs)))
+            }
+            Expr::Div(lhs, rhs) => {
+                let (lhs, rhs) = binary(lhs, rhs)?;
+                lhs.checked_div(rhs)
+                    .ok_or_else(|| env.error(format!("{} / {} divides by zero", lhs, rhs)))
+            }
+            Expr::Align(value, alignment) => {
+                let (value, alignment) = binary(value, alignment)?;
+                if alignment == 0 {
+                    return Err(env.error("Alignment 0".to_string()));
+                }
+                value
+                    .checked_add((alignment - value % alignment) % alignment)
+                    .ok_or_else(|| env.error(format!("align({}, {}) overflows", value, alignment)))
+            }
+        }
+    }
+}
+
+/// Offset or size given on the command line
+///
+/// Either a plain number or an expression of numbers, `end`, `sym:<name>` and `align(..)`.
+#[derive(Debug)]
 pub struct ByteOffset {
-    num: u64,
-    magnitude: Magnitude,
+    text: String,
+    expr: Expr,
+}
+
+impl Default for ByteOffset {
+    fn default() -> Self {
+        ByteOffset::new(0, Magnitude::Unit)
+    }
+}
+
+impl fmt::Display for ByteOffset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
 }
 
 impl ByteOffset {
     pub fn new(num: u64, magnitude: Magnitude) -> Self {
-        Self { num, magnitude }
+        Self {
+            text: format!("{}{}", num, magnitude.suffix()),
+            expr: Expr::Number(num, magnitude),
+        }
     }
-    pub fn as_u64(&self) -> u64 {
-        self.magnitude.as_u64() * self.num
+
+    /// Parse an offset expression
+    ///
+    /// A number is decimal, or hexadecimal, octal or binary with a `0x`, `0o` or `0b` prefix.
+    /// Digits may be separated by `_` and followed by a magnitude such as `Ki`.
+    /// As `E` is a hexadecimal digit, hexadecimal numbers can not be followed by `E`.
+    /// Numbers, `end` (the length of the input), `sym:<name>` and `align(<value>, <alignment>)`
+    /// can be combined with `+`, `-`, `*`, `/` and parentheses.
+    /// A leading `-` is relative to the end of the input, so `-64` is the same as `end-64`.
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut scanner = Scanner::new(value);
+        let expr = scanner.expr()?;
+        scanner.skip_whitespace();
+        if scanner.peek().is_some() {
+            return Err(scanner.error("expected an operator"));
+        }
+        Ok(ByteOffset { text: value.to_string(), expr })
     }
-    pub fn as_usize(&self) -> usize {
-        self.magnitude.as_usize() * (self.num as usize)
+
+    /// Resolve to an absolute offset
+    ///
+    /// `end` is the length of the input, symbols are looked up in `symbols`
+    /// and translated to their offset within that ELF file.
+    pub fn resolve(&self, end: Option<u64>, symbols: Option<&Elf>) -> Result<u64> {
+        self.expr.evaluate(&Environment {
+            text: &self.text,
+            end,
+            symbols,
+        })
     }
 }
 
@@ -93,26 +467,7 @@ impl<'de> de::Deserialize<'de> for ByteOffset {
             where
                 E: de::Error,
             {
-                lazy_static!{
-                    static ref REGEX : Regex = Regex::new(r"^([0-9]+)((?:[KMGTE]i?)?)$").unwrap();
-                }
-                
-                let byte_offset = REGEX.captures(value).ok_or(Err::<Captures,ScalpelError>(ScalpelError::ParsingError{r: "".to_string()} ) )
-                .and_then(|captures| {
-                    if captures.len() == 3 {
-                        let num_str = &captures[1];
-                        let magnitude_str = &captures[2];
-                        let num : u64 = num_str
-                            .parse::<u64>()
-                            .map_err(|e| Err::<Captures,ScalpelError>(ScalpelError::ParsingError{r: format!("Failed to parse u64 {}", e)}) )?;
-                        let magnitude = Magnitude::parse(magnitude_str)
-                            .map_err(|e| Err::<Captures,ScalpelError>(ScalpelError::ParsingError{r: format!("Failed to parse magnitude {}", e)}) )?;
-                        Ok(ByteOffset::new(num, magnitude))
-                    } else {
-                        Ok(Default::default())
-                    }
-                }).map_err(|e| E::custom(format!("{:?}", e) ))?;
-                Ok(byte_offset)
+                ByteOffset::parse(value).map_err(|e| E::custom(format!("{}", e)))
             }
 
         }
@@ -120,6 +475,70 @@ impl<'de> de::Deserialize<'de> for ByteOffset {
     }
 }
 
+/// Range of bytes given as `<start>..<end>`, the end is exclusive
+#[derive(Debug)]
+pub struct ByteRange {
+    pub start: ByteOffset,
+    pub end: ByteOffset,
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+impl ByteRange {
+    /// Parse two offset expressions separated by `..`
+    pub fn parse(value: &str) -> Result<Self> {
+        let separator = value.find("..").ok_or_else(|| {
+            ScalpelError::ArgumentError.context(format!("expected <start>..<end> in range \"{}\"", value))
+        })?;
+        Ok(ByteRange {
+            start: ByteOffset::parse(&value[..separator])?,
+            end: ByteOffset::parse(&value[separator + 2..])?,
+        })
+    }
+
+    /// Resolve to absolute start and end offsets, see `ByteOffset::resolve`
+    pub fn resolve(&self, end: Option<u64>, symbols: Option<&Elf>) -> Result<(u64, u64)> {
+        let range = (self.start.resolve(end, symbols)?, self.end.resolve(end, symbols)?);
+        if range.0 > range.1 {
+            return Err(ScalpelError::ArgumentError
+                .context(format!("range {} is resolved to {}..{}, the end is before the start", self, range.0, range.1))
+                .into());
+        }
+        Ok(range)
+    }
+}
+
+impl<'de> de::Deserialize<'de> for ByteRange {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+
+        struct ByteRangeVisitor;
+
+        impl<'de> de::Visitor<'de> for ByteRangeVisitor {
+            type Value = ByteRange;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expected a ByteRange")
+            }
+
+            fn visit_str<E>(self, value: &str) -> ::std::result::Result<ByteRange, E>
+            where
+                E: de::Error,
+            {
+                ByteRange::parse(value).map_err(|e| E::custom(format!("{}", e)))
+            }
+
+        }
+        deserializer.deserialize_str(ByteRangeVisitor)
+    }
+}
+
 // Old Stuff
 // fn deserialize_suffix( n: &str) -> Result<u64> 
 // {
@@ -146,4 +565,155 @@ impl<'de> de::Deserialize<'de> for ByteOffset {
 //     let val: u64 = flag.trim_matches(char::is_alphabetic).parse()?;
 
 //     Ok(val * suffix)
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use elf::test::Builder;
+
+    #[test]
+    fn parse_plain() {
+        assert_eq!(ByteOffset::parse("4Ki").expect("Failed to parse").resolve(None, None).unwrap(), 4096);
+        assert!(ByteOffset::parse("4Kb").is_err());
+    }
+
+    #[test]
+    fn large_magnitudes() {
+        let parse = |value| ByteOffset::parse(value).expect("Failed to parse").resolve(None, None);
+        assert_eq!(parse("2T").unwrap(), 2_000_000_000_000);
+        assert_eq!(parse("2Ti").unwrap(), 2 << 40);
+        assert_eq!(parse("3P").unwrap(), 3_000_000_000_000_000);
+        assert_eq!(parse("3Pi").unwrap(), 3 << 50);
+        assert_eq!(parse("18E").unwrap(), 18_000_000_000_000_000_000);
+        assert_eq!(parse("15Ei").unwrap(), 15 << 60);
+        assert_eq!(parse("0b1Ei").unwrap(), 1 << 60);
+        assert_eq!(parse("0x1E").unwrap(), 0x1E);
+
+        assert!(parse("19E").is_err());
+        assert!(parse("16Ei").is_err());
+        assert!(parse("16385Pi").is_err());
+        assert!(ByteOffset::parse("1Ex").is_err());
+    }
+
+    #[test]
+    fn parse_radix() {
+        let parse = |value| ByteOffset::parse(value).expect("Failed to parse").resolve(None, None).unwrap();
+        assert_eq!(parse("0x8000"), 0x8000);
+        assert_eq!(parse("0XfF"), 0xFF);
+        assert_eq!(parse("0o17"), 0o17);
+        assert_eq!(parse("0b1010"), 0b1010);
+        assert_eq!(parse("1_000Ki"), 1_024_000);
+        assert_eq!(parse("0x1_0000"), 0x1_0000);
+        assert_eq!(parse("0x10Ki"), 0x4000);
+        assert_eq!(parse("0xFFFF_FFFF_FFFF_FFFF"), u64::MAX);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |value| format!("{}", ByteOffset::parse(value).expect_err("Accepted garbage"));
+        assert!(error("0x1g").contains("found 'g' at position 4"));
+        assert!(error("0b102").contains("expected a binary digit, found '2' at position 5"));
+        assert!(error("0o8").contains("expected an octal digit"));
+        assert!(error("12Kb").contains("found 'b' at position 4"));
+        assert!(error("0x").contains("found end of input at position 3"));
+        assert!(error("_1").contains("at position 1"));
+        assert!(error("0x1_0000_0000_0000_0000").contains("does not fit into 64 bits"));
+        assert!(error("sym:").contains("expected a symbol name"));
+    }
+
+    #[test]
+    fn expressions() {
+        let eval = |value| {
+            ByteOffset::parse(value)
+                .expect("Failed to parse expression")
+                .resolve(Some(2048), None)
+        };
+        assert_eq!(eval("end-64").unwrap(), 1984);
+        assert_eq!(eval("(256Ki-4Ki)").unwrap(), 252 * 1024);
+        assert_eq!(eval("2+3*4").unwrap(), 14);
+        assert_eq!(eval("(2+3)*4").unwrap(), 20);
+        assert_eq!(eval("10-4-3").unwrap(), 3);
+        assert_eq!(eval("end / 2 - 0x10").unwrap(), 1008);
+        assert_eq!(eval("align(1000, 4Ki)").unwrap(), 4096);
+        assert_eq!(eval("align(end, 1Ki)").unwrap(), 2048);
+        assert_eq!(eval("align(end+1, 0x400)").unwrap(), 3072);
+        // whitespace beyond ASCII
+        assert_eq!(eval("1\u{a0}+\u{2003}1").unwrap(), 2);
+
+        assert!(eval("64-end").is_err());
+        assert!(eval("0xFFFF_FFFF_FFFF_FFFF+1").is_err());
+        assert!(eval("0x1_0000_0000*0x1_0000_0000").is_err());
+        assert!(eval("16Gi*1Gi").is_err());
+        assert!(eval("end/0").is_err());
+        assert!(eval("align(end, 0)").is_err());
+        assert!(ByteOffset::parse("end").unwrap().resolve(None, None).is_err());
+    }
+
+    #[test]
+    fn from_end() {
+        let eval = |value| {
+            ByteOffset::parse(value)
+                .expect("Failed to parse negative offset")
+                .resolve(Some(2112), None)
+        };
+        assert_eq!(eval("-64").unwrap(), 2048);
+        assert_eq!(eval("-0x40").unwrap(), 2048);
+        assert_eq!(eval("-2*32").unwrap(), 2048);
+        assert_eq!(eval("-64+16").unwrap(), 2064);
+        assert_eq!(eval("-end").unwrap(), 0);
+        assert_eq!(eval("align(-100, 64)").unwrap(), 2048);
+        assert!(eval("-4Ki").is_err());
+        assert!(ByteOffset::parse("-64").unwrap().resolve(None, None).is_err());
+        assert!(ByteOffset::parse("--64").is_err());
+
+        let error = |value| format!("{}", ByteOffset::parse(value).expect_err("Accepted garbage"));
+        assert!(error("(1+2").contains("expected ')', found end of input at position 5"));
+        assert!(error("1+").contains("at position 3"));
+        assert!(error("2 3").contains("expected an operator, found '3' at position 3"));
+        assert!(error("ending").contains("at position 1"));
+        assert!(error("align(4)").contains("expected ','"));
+    }
+
+    #[test]
+    fn resolve_symbols() {
+        let elf = Elf::parse(Builder::firmware(false, true).bytes).expect("Failed to parse ELF");
+
+        // app_header is linked to 0x1004, which is located at file offset 0x104
+        let offset = ByteOffset::parse("sym:app_header").expect("Failed to parse symbol");
+        assert_eq!(offset.resolve(None, Some(&elf)).unwrap(), 0x104);
+        let offset = ByteOffset::parse("sym:app_header+2").expect("Failed to parse symbol");
+        assert_eq!(offset.resolve(None, Some(&elf)).unwrap(), 0x106);
+        let offset = ByteOffset::parse("sym:app_header-4").expect("Failed to parse symbol");
+        assert_eq!(offset.resolve(None, Some(&elf)).unwrap(), 0x100);
+
+        // symbols in .data are linked to their run time address
+        let offset = ByteOffset::parse("sym:counter+1").expect("Failed to parse symbol");
+        assert_eq!(offset.resolve(None, Some(&elf)).unwrap(), 0x10A);
+
+        assert!(offset.resolve(None, None).is_err());
+        assert!(ByteOffset::parse("sym:app_header-0x200").unwrap().resolve(None, Some(&elf)).is_err());
+        assert!(ByteOffset::parse("sym:nothing").unwrap().resolve(None, Some(&elf)).is_err());
+        assert!(ByteOffset::parse("sym:+4").is_err());
+
+        let offset = ByteOffset::parse("align(sym:app_header, 8) + 0x0_2 * 2").expect("Failed to parse symbol");
+        assert_eq!(offset.resolve(None, Some(&elf)).unwrap(), 0x10C);
+    }
+
+    #[test]
+    fn ranges() {
+        let eval = |value| {
+            ByteRange::parse(value)
+                .expect("Failed to parse range")
+                .resolve(Some(0x1000), None)
+        };
+        assert_eq!(eval("0..end").unwrap(), (0, 0x1000));
+        assert_eq!(eval("0x100..-64").unwrap(), (0x100, 0xFC0));
+        assert_eq!(eval("1Ki .. 2Ki").unwrap(), (1024, 2048));
+        assert_eq!(eval("8..8").unwrap(), (8, 8));
+        assert!(eval("2Ki..1Ki").is_err());
+        assert!(ByteRange::parse("0-100").is_err());
+        assert!(ByteRange::parse("0..").is_err());
+        assert_eq!(format!("{}", ByteRange::parse("0x10..end-4").unwrap()), "0x10..end-4");
+    }
+}
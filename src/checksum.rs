@@ -0,0 +1,292 @@
+use std::path::Path;
+
+use concat;
+use embed::{self, Region};
+use errors::*;
+use stitch::read_file;
+
+/// Byte order a checksum is written in
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Parameters of a CRC in the Rocksoft model
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crc {
+    /// number of bits, one of 8, 16, 32 or 64
+    pub width: u32,
+    pub poly: u64,
+    pub init: u64,
+    /// reflect each input byte
+    pub refin: bool,
+    /// reflect the result before `xorout`
+    pub refout: bool,
+    pub xorout: u64,
+}
+
+/// Named CRCs as catalogued by reveng, with their aliases
+const PRESETS: &[(&[&str], Crc)] = &[
+    (&["CRC-8", "CRC-8/SMBUS"], Crc { width: 8, poly: 0x07, init: 0, refin: false, refout: false, xorout: 0 }),
+    (&["CRC-8/MAXIM-DOW", "CRC-8/MAXIM", "DOW-CRC"], Crc { width: 8, poly: 0x31, init: 0, refin: true, refout: true, xorout: 0 }),
+    (&["CRC-16/CCITT-FALSE", "CRC-16/IBM-3740", "CRC-16/AUTOSAR"], Crc { width: 16, poly: 0x1021, init: 0xFFFF, refin: false, refout: false, xorout: 0 }),
+    (&["CRC-16/ARC", "CRC-16", "CRC-16/IBM"], Crc { width: 16, poly: 0x8005, init: 0, refin: true, refout: true, xorout: 0 }),
+    (&["CRC-16/XMODEM", "XMODEM", "CRC-16/ACORN"], Crc { width: 16, poly: 0x1021, init: 0, refin: false, refout: false, xorout: 0 }),
+    (&["CRC-16/KERMIT", "KERMIT", "CRC-16/CCITT"], Crc { width: 16, poly: 0x1021, init: 0, refin: true, refout: true, xorout: 0 }),
+    (&["CRC-16/MODBUS", "MODBUS"], Crc { width: 16, poly: 0x8005, init: 0xFFFF, refin: true, refout: true, xorout: 0 }),
+    (&["CRC-32/ISO-HDLC", "CRC-32", "CRC-32/ADCCP", "PKZIP"], Crc { width: 32, poly: 0x04C1_1DB7, init: 0xFFFF_FFFF, refin: true, refout: true, xorout: 0xFFFF_FFFF }),
+    (&["CRC-32C", "CRC-32/ISCSI", "CRC-32/CASTAGNOLI"], Crc { width: 32, poly: 0x1EDC_6F41, init: 0xFFFF_FFFF, refin: true, refout: true, xorout: 0xFFFF_FFFF }),
+    (&["CRC-32/BZIP2", "CRC-32/AAL5"], Crc { width: 32, poly: 0x04C1_1DB7, init: 0xFFFF_FFFF, refin: false, refout: false, xorout: 0xFFFF_FFFF }),
+    (&["CRC-32/MPEG-2"], Crc { width: 32, poly: 0x04C1_1DB7, init: 0xFFFF_FFFF, refin: false, refout: false, xorout: 0 }),
+    (&["CRC-64/XZ", "CRC-64/GO-ECMA"], Crc { width: 64, poly: 0x42F0_E1EB_A9EA_3693, init: u64::MAX, refin: true, refout: true, xorout: u64::MAX }),
+    (&["CRC-64/ECMA-182", "CRC-64"], Crc { width: 64, poly: 0x42F0_E1EB_A9EA_3693, init: 0, refin: false, refout: false, xorout: 0 }),
+];
+
+impl Crc {
+    pub fn new(width: u32, poly: u64, init: u64, refin: bool, refout: bool, xorout: u64) -> Result<Self> {
+        let crc = Crc { width, poly, init, refin, refout, xorout };
+        if ![8, 16, 32, 64].contains(&width) {
+            return Err(ScalpelError::ArgumentError
+                .context(format!("CRC width {} is not one of 8, 16, 32 or 64", width))
+                .into());
+        }
+        for (name, value) in &[("poly", poly), ("init", init), ("xorout", xorout)] {
+            if value & !crc.mask() != 0 {
+                return Err(ScalpelError::ArgumentError
+                    .context(format!("CRC {} {:#X} exceeds the width of {} bits", name, value, width))
+                    .into());
+            }
+        }
+        Ok(crc)
+    }
+
+    /// look up a named CRC such as CRC-32/ISO-HDLC, case insensitive
+    pub fn preset(name: &str) -> Result<Self> {
+        PRESETS
+            .iter()
+            .find(|(names, _)| names.iter().any(|preset| preset.eq_ignore_ascii_case(name)))
+            .map(|(_, crc)| *crc)
+            .ok_or_else(|| {
+                let known = PRESETS.iter().map(|(names, _)| names[0]).collect::<Vec<_>>().join(", ");
+                ScalpelError::ArgumentError
+                    .context(format!("Unknown CRC {}, known are {}", name, known))
+                    .into()
+            })
+    }
+
+    fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.width)
+    }
+
+    /// number of bytes of the checksum
+    pub fn len(&self) -> usize {
+        self.width as usize / 8
+    }
+
+    pub fn checksum(&self, bytes: &[u8]) -> u64 {
+        let top = 1u64 << (self.width - 1);
+        let mut crc = self.init;
+        for byte in bytes {
+            let byte = if self.refin { byte.reverse_bits() } else { *byte };
+            crc ^= u64::from(byte) << (self.width - 8);
+            for _ in 0..8 {
+                crc = if crc & top != 0 { (crc << 1) ^ self.poly } else { crc << 1 };
+            }
+            crc &= self.mask();
+        }
+        if self.refout {
+            crc = crc.reverse_bits() >> (64 - self.width);
+        }
+        crc ^ self.xorout
+    }
+
+    /// the checksum `value` as `len()` bytes in `endian` byte order
+    pub fn encode(&self, value: u64, endian: Endian) -> Vec<u8> {
+        match endian {
+            Endian::Little => value.to_le_bytes()[..self.len()].to_vec(),
+            Endian::Big => value.to_be_bytes()[8 - self.len()..].to_vec(),
+        }
+    }
+
+    /// the checksum stored as `len()` bytes in `endian` byte order
+    pub fn decode(&self, bytes: &[u8], endian: Endian) -> u64 {
+        let fold = |value: u64, byte: &u8| (value << 8) | u64::from(*byte);
+        match endian {
+            Endian::Little => bytes[..self.len()].iter().rev().fold(0, fold),
+            Endian::Big => bytes[..self.len()].iter().fold(0, fold),
+        }
+    }
+}
+
+/// parse a CRC parameter such as `0x04C11DB7` or `4129`
+pub fn parse_parameter(value: &str) -> Result<u64> {
+    let parsed = if value.starts_with("0x") || value.starts_with("0X") {
+        u64::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse()
+    };
+    parsed.map_err(|err| {
+        ScalpelError::ParsingError { r: format!("Invalid CRC parameter {}: {}", value, err) }.into()
+    })
+}
+
+/// Where the checksum goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    /// into the slot at this offset, which is excluded from the checksum
+    Embed(u64),
+    Append,
+}
+
+/// calculate the CRC of `start..end` of the file at `path`, the embedding slot is left out
+pub fn checksum_file(crc: &Crc, path: &Path, start: u64, end: u64, placement: Option<Placement>) -> Result<u64> {
+    let bytes = read_file(path)?;
+    let slot = match placement {
+        Some(Placement::Embed(slot)) => Some(slot),
+        _ => None,
+    };
+    let region = Region::new(start, end, slot, crc.len(), bytes.len() as u64)
+        .map_err(|err| err.context(format!("{:?}", path)))?;
+    Ok(crc.checksum(&region.message(&bytes)))
+}
+
+/// compare the CRC of `start..end` of the file at `path` with the one stored at `slot` in `endian`,
+/// the slot is left out of the range
+pub fn verify_checksum(crc: &Crc, path: &Path, start: u64, end: u64, slot: u64, endian: Endian) -> Result<u64> {
+    let bytes = read_file(path)?;
+    let region = Region::new(start, end, Some(slot), crc.len(), bytes.len() as u64)
+        .map_err(|err| err.context(format!("{:?}", path)))?;
+    let actual = crc.checksum(&region.message(&bytes));
+    let expected = crc.decode(region.slot_bytes(&bytes), endian);
+    if expected != actual {
+        let digits = 2 + 2 * crc.len();
+        return Err(ScalpelError::ChecksumMismatch {
+            expected: format!("{:#0digits$X}", expected, digits = digits),
+            actual: format!("{:#0digits$X}", actual, digits = digits),
+            offset: slot,
+        }
+        .into());
+    }
+    Ok(actual)
+}
+
+/// write the checksum `value` of the file at `path` to `output`, which may be the file itself
+pub fn write_checksum(crc: &Crc, value: u64, endian: Endian, path: &Path, output: &Path, placement: Placement) -> Result<()> {
+    let checksum = crc.encode(value, endian);
+    match placement {
+        Placement::Append => concat::append_signature(path, output, &checksum),
+        Placement::Embed(slot) => embed::write_slot(read_file(path)?, slot, &checksum, output),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::TempFile;
+
+    #[test]
+    fn check_values() {
+        // the checksum of "123456789" as given by the catalogue
+        let check = [
+            ("CRC-8/SMBUS", 0xF4),
+            ("CRC-8/MAXIM-DOW", 0xA1),
+            ("CRC-16/CCITT-FALSE", 0x29B1),
+            ("CRC-16/ARC", 0xBB3D),
+            ("CRC-16/XMODEM", 0x31C3),
+            ("CRC-16/KERMIT", 0x2189),
+            ("CRC-16/MODBUS", 0x4B37),
+            ("CRC-32/ISO-HDLC", 0xCBF4_3926),
+            ("CRC-32C", 0xE306_9283),
+            ("CRC-32/BZIP2", 0xFC89_1918),
+            ("CRC-32/MPEG-2", 0x0376_E6E7),
+            ("CRC-64/XZ", 0x995D_C9BB_DF19_39FA),
+            ("CRC-64/ECMA-182", 0x6C40_DF5F_0B49_7347),
+        ];
+        for (name, value) in check.iter() {
+            let crc = Crc::preset(name).expect("Unknown preset");
+            assert_eq!(crc.checksum(b"123456789"), *value, "{}", name);
+        }
+        assert_eq!(Crc::preset("crc-32c").unwrap(), Crc::preset("CRC-32/ISCSI").unwrap());
+        assert!(Crc::preset("CRC-31").is_err());
+
+        let custom = Crc::new(16, 0x1021, 0xFFFF, false, false, 0).expect("Failed to create CRC");
+        assert_eq!(custom, Crc::preset("CRC-16/CCITT-FALSE").unwrap());
+        assert!(Crc::new(12, 0x80F, 0, false, false, 0).is_err());
+        assert!(Crc::new(8, 0x107, 0, false, false, 0).is_err());
+        assert_eq!(parse_parameter("0x1EDC6F41").unwrap(), 0x1EDC_6F41);
+        assert_eq!(parse_parameter("4129").unwrap(), 0x1021);
+        assert!(parse_parameter("0xG").is_err());
+    }
+
+    #[test]
+    fn endianness() {
+        let crc = Crc::preset("CRC-32").unwrap();
+        assert_eq!(crc.encode(0xCBF4_3926, Endian::Little), [0x26, 0x39, 0xF4, 0xCB]);
+        assert_eq!(crc.encode(0xCBF4_3926, Endian::Big), [0xCB, 0xF4, 0x39, 0x26]);
+        let crc = Crc::preset("CRC-16/ARC").unwrap();
+        assert_eq!(crc.encode(0xBB3D, Endian::Big), [0xBB, 0x3D]);
+        for endian in &[Endian::Little, Endian::Big] {
+            assert_eq!(crc.decode(&crc.encode(0xBB3D, *endian), *endian), 0xBB3D);
+        }
+        let crc = Crc::preset("CRC-64/XZ").unwrap();
+        assert_eq!(crc.decode(&crc.encode(0x995D_C9BB_DF19_39FA, Endian::Little), Endian::Little), 0x995D_C9BB_DF19_39FA);
+    }
+
+    #[test]
+    fn embed_and_append() {
+        let image = TempFile::copy("tmp/test_bytes", "tmp/test_checksum.bin");
+        let path = image.path();
+        let original = read_file(path).expect("Failed to read file");
+        let crc = Crc::preset("CRC-32C").unwrap();
+
+        // the slot within the range is left out
+        let value = checksum_file(&crc, path, 0, 2048, Some(Placement::Embed(16))).expect("Failed to calculate CRC");
+        assert_eq!(value, crc.checksum(&[&original[..16], &original[20..]].concat()));
+        write_checksum(&crc, value, Endian::Big, path, path, Placement::Embed(16)).expect("Failed to embed CRC");
+        let embedded = read_file(path).expect("Failed to read file");
+        assert_eq!(embedded[16..20], crc.encode(value, Endian::Big)[..]);
+        assert_eq!(checksum_file(&crc, path, 0, 2048, Some(Placement::Embed(16))).unwrap(), value);
+
+        let value = checksum_file(&crc, path, 0, 2048, Some(Placement::Append)).expect("Failed to calculate CRC");
+        write_checksum(&crc, value, Endian::Little, path, path, Placement::Append).expect("Failed to append CRC");
+        let appended = read_file(path).expect("Failed to read file");
+        assert_eq!(appended.len(), 2052);
+        assert_eq!(appended[2048..], crc.encode(value, Endian::Little)[..]);
+
+        assert!(checksum_file(&crc, path, 0, 4096, None).is_err());
+        assert!(checksum_file(&crc, path, 0, 16, Some(Placement::Embed(2050))).is_err());
+    }
+
+    #[test]
+    fn verify_stored() {
+        let image = TempFile::copy("tmp/test_bytes", "tmp/test_checksum_verify.bin");
+        let path = image.path();
+        let crc = Crc::preset("CRC-16/CCITT-FALSE").unwrap();
+
+        let value = checksum_file(&crc, path, 0, 2048, Some(Placement::Embed(0x1C))).expect("Failed to calculate CRC");
+        write_checksum(&crc, value, Endian::Big, path, path, Placement::Embed(0x1C)).expect("Failed to embed CRC");
+        assert_eq!(verify_checksum(&crc, path, 0, 2048, 0x1C, Endian::Big).expect("Verification failed"), value);
+        // wrong byte order
+        if value.swap_bytes() >> 48 != value {
+            assert!(verify_checksum(&crc, path, 0, 2048, 0x1C, Endian::Little).is_err());
+        }
+
+        // an appended checksum is the slot at the end of the range
+        write_checksum(&crc, value, Endian::Little, path, path, Placement::Append).expect("Failed to append CRC");
+        assert!(verify_checksum(&crc, path, 0, 2048, 2048, Endian::Little).is_err());
+        let appended = checksum_file(&crc, path, 0, 2048, None).expect("Failed to calculate CRC");
+        write_checksum(&crc, appended, Endian::Little, path, path, Placement::Embed(2048)).expect("Failed to embed CRC");
+        assert!(verify_checksum(&crc, path, 0, 2050, 2048, Endian::Little).is_ok());
+
+        // corrupted
+        write_checksum(&crc, appended ^ 1, Endian::Little, path, path, Placement::Embed(2048)).expect("Failed to embed CRC");
+        let err = verify_checksum(&crc, path, 0, 2050, 2048, Endian::Little).unwrap_err();
+        assert_eq!(
+            format!("{}", err),
+            format!("Checksum mismatch, expected {:#06X} as stored at 0x800, actual {:#06X}", appended ^ 1, appended)
+        );
+        assert!(verify_checksum(&crc, path, 0, 2050, 2049, Endian::Little).is_err());
+    }
+}
@@ -1,13 +1,27 @@
-use ring::signature;
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
-use std::path::Path;
-use bytes::Bytes;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
 use errors::*;
+use signer::{decode_pem, encode_pem};
+
+/// Encodings of detached signature files
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum SignatureFormat {
+    #[default]
+    Raw,
+    Base64,
+    /// base64 armored as `SIGNATURE` block
+    Pem,
+}
 
 /// open output file, add "-signed" to name
 pub fn derive_output_filename(path: &Path) -> Result<String> {
+    derive_filename(path, "signed")
+}
+
+/// add "-<suffix>" to the name in front of the extension
+pub fn derive_filename(path: &Path, suffix: &str) -> Result<String> {
     // get file
     let filename = path.to_str()
         .ok_or::<Error>(ScalpelError::PathError.into())?;
@@ -15,47 +29,114 @@ pub fn derive_output_filename(path: &Path) -> Result<String> {
     let file_split: Vec<&str> = filename.rsplitn(2, '.').collect();
 
     Ok(if file_split.len() > 1 {
-        format!("{}-signed.{}", file_split[1], file_split[0])
+        format!("{}-{}.{}", file_split[1], suffix, file_split[0])
     } else {
-        format!("{}-signed", file_split[0])
+        format!("{}-{}", file_split[0], suffix)
     })
 }
 
-/// takes a file and creates a copy with signature appended
-pub fn append_signature(path: &Path, sig: &signature::Signature) -> Result<()> {
-    let file_sig = derive_output_filename(path)?;
+/// whether `output` is the file at `path` itself
+pub fn is_same_file(path: &Path, output: &Path) -> bool {
+    path == output
+        || match (fs::canonicalize(path), fs::canonicalize(output)) {
+            (Ok(path), Ok(output)) => path == output,
+            _ => false,
+        }
+}
+
+/// takes a file and writes a copy with signature appended to `output`,
+/// the signature is appended to the file itself if `output` is the same file
+pub fn append_signature(path: &Path, output: &Path, sig: &[u8]) -> Result<()> {
+    let mut f_out = if is_same_file(path, output) {
+        OpenOptions::new()
+            .append(true)
+            .open(output)
+            .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, output)))?
+    } else {
+        // create output file
+        let mut f_out = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(output)
+            .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, output)))?;
+
+        // open input file
+        let mut f_in = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path)))?;
+
+        // copy input to new file without reading all of it at once
+        io::copy(&mut f_in, &mut f_out)
+            .map_err(|err| ScalpelError::WritingError.context(err))?;
+        f_out
+    };
+
+    f_out
+        .write_all(sig)
+        .map_err(|err| ScalpelError::WritingError.context(err))?;
+
+    Ok(())
+}
+
+/// path of the detached signature of `path`, `<file>.sig`
+pub fn derive_signature_filename(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+/// write `sig` to `output`, usually `<file>.sig`, and leave the file itself untouched
+pub fn write_detached_signature(output: &Path, sig: &[u8], format: SignatureFormat) -> Result<()> {
+    let content = match format {
+        SignatureFormat::Raw => sig.to_vec(),
+        SignatureFormat::Base64 => format!("{}\n", base64::encode(sig)).into_bytes(),
+        SignatureFormat::Pem => encode_pem(sig, "SIGNATURE").into_bytes(),
+    };
 
-    // create output file
     let mut f_out = OpenOptions::new()
         .write(true)
         .truncate(true)
         .create(true)
-        .open(Path::new(&file_sig))
-        .map_err(|err| ScalpelError::OpeningError.context(err))?;
+        .open(output)
+        .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, output)))?;
+
+    f_out
+        .write_all(&content)
+        .map_err(|err| ScalpelError::WritingError.context(err))?;
+
+    Ok(())
+}
 
-    // open input file
+/// read a detached signature of `len` bytes, the encoding is detected
+pub fn read_detached_signature(path: &Path, len: usize) -> Result<Vec<u8>> {
     let mut f_in = OpenOptions::new()
         .read(true)
         .open(path)
-        .map_err(|err| ScalpelError::OpeningError.context(err))?;
+        .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path)))?;
 
-    // read input file to buffer
     let mut content: Vec<u8> = Vec::new();
     f_in.read_to_end(&mut content)
         .map_err(|err| ScalpelError::ReadingError.context(err))?;
 
-    // write input to new file, afterwards append signature
-    f_out
-        .write_all(&content)
-        .map_err(|err| ScalpelError::WritingError.context(err))?;
-
-    let byte_sig = Bytes::from(sig.as_ref());
-
-    f_out
-        .write_all(&byte_sig)
-        .map_err(|err| ScalpelError::WritingError.context(err))?;
-
-    Ok(())
+    let sig = if content.starts_with(b"-----BEGIN") {
+        decode_pem(&String::from_utf8_lossy(&content), "SIGNATURE")?
+    } else if content.len() == len {
+        content
+    } else {
+        let text = String::from_utf8_lossy(&content);
+        base64::decode(text.trim()).map_err(|err| {
+            ScalpelError::ContentError.context(format!("{:?} is neither raw, base64 nor PEM: {}", path, err))
+        })?
+    };
+
+    if sig.len() != len {
+        return Err(ScalpelError::ContentError
+            .context(format!("Signature {:?} has {} bytes, expected {}", path, sig.len(), len))
+            .into());
+    }
+    Ok(sig)
 }
 
 #[cfg(test)]
@@ -66,6 +147,8 @@ mod test {
     use std::iter;
     use signer::*;
     use std::io::{Seek, SeekFrom};
+    use bytes::Bytes;
+    use testing::TempFile;
 
     #[test]
     fn test_append_signature() {
@@ -73,32 +156,70 @@ mod test {
 
         //random content generation
         let mut rng = rand::thread_rng();
-        let byte_victim = iter::repeat(1)
-            .take(1000)
+        let byte_victim = iter::repeat_n(1, 1000)
             .map(|_| rng.gen_range(1, 255))
             .collect::<Bytes>();
         let signature = signer.calculate_signature(&byte_victim)
             .expect("Failed signature from bytes");
         let path_victim = Path::new("tmp/test_bytes");
-        append_signature(&path_victim, &signature).expect("Appending signature failed.");
+        let output = derive_output_filename(path_victim).expect("Failed to derive output name");
+        assert_eq!(output, "tmp/test_bytes-signed");
+        append_signature(path_victim, Path::new(&output), &signature).expect("Appending signature failed.");
 
-        // open signed file and compare signature, path is hardcoded
-        let path_victim = Path::new("tmp/test_bytes-signed");
+        // open signed file and compare signature
+        let path_victim = Path::new(&output);
         let mut f_in = OpenOptions::new()
             .read(true)
-            .open(&path_victim)
+            .open(path_victim)
             .expect("Failed to read signed File");
         // read from end of file for the length of singature
-        let ref_sig = signature.as_ref();
+        let ref_sig = &signature[..];
         let mut read_sig = vec![0; ref_sig.len()];
 
         f_in.seek(SeekFrom::End(-(ref_sig.len() as i64)))
             .expect("Failed to seek from end");
-        f_in.read(&mut read_sig).expect("Failed to Read Signature");
+        f_in.read_exact(&mut read_sig).expect("Failed to Read Signature");
         println!("{}", read_sig.len());
 
         assert_eq!(ref_sig[..], read_sig[..]);
     }
 
-    
+    #[test]
+    fn append_in_place() {
+        let image = TempFile::copy("tmp/test_bytes", "tmp/test_in_place.bin");
+        let path = image.path();
+        let output = TempFile::new("tmp/test_in_place-signed.bin");
+        append_signature(path, Path::new("tmp/../tmp/test_in_place.bin"), &[0xAB; 64]).expect("Appending signature failed.");
+        append_signature(path, output.path(), &[0xCD; 64]).expect("Appending signature failed.");
+
+        let signed = ::stitch::read_file(path).expect("Failed to read file");
+        assert_eq!(signed.len(), 2048 + 64);
+        assert_eq!(signed[2048..], [0xAB; 64][..]);
+        let copy = ::stitch::read_file(output.path()).expect("Failed to read file");
+        assert_eq!(copy.len(), 2048 + 128);
+        assert_eq!(copy[..2112], signed[..]);
+    }
+
+    #[test]
+    fn detached_signature() {
+        let signer = Signer::random();
+        let verifier = signer.verifier().expect("Failed to get verifier");
+        let image = TempFile::copy("tmp/test_bytes", "tmp/test_detached.bin");
+        let path = image.path();
+        let _sig = TempFile::new("tmp/test_detached.bin.sig");
+        let signature = signer.calculate_signature_of_file(path).expect("Signing failed");
+
+        for format in &[SignatureFormat::Raw, SignatureFormat::Base64, SignatureFormat::Pem] {
+            let file_sig = derive_signature_filename(path);
+            assert_eq!(file_sig, Path::new("tmp/test_detached.bin.sig"));
+            write_detached_signature(&file_sig, &signature, *format).expect("Failed to write signature");
+            let read_sig = read_detached_signature(&file_sig, 64).expect("Failed to read signature");
+            assert_eq!(read_sig, signature);
+            assert!(verifier.verify_detached(path, &read_sig).is_ok());
+            assert!(read_detached_signature(&file_sig, 256).is_err());
+        }
+        // the image itself stays untouched
+        assert_eq!(::std::fs::metadata(path).expect("Failed to stat image").len(), 2048);
+        assert!(verifier.verify_detached("tmp/signme.bin", &signature).is_err());
+    }
 }
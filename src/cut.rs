@@ -2,6 +2,7 @@ use std::fs::OpenOptions;
 use std::io::{Read, Seek, SeekFrom, Write};
 
 use errors::*;
+use format::Image;
 
 pub fn cut_out_bytes(
     victim: String,
@@ -27,24 +28,42 @@ pub fn cut_out_bytes(
         .map_err(|err| ScalpelError::SeekError.context(err))?;
 
     let mut remaining = size;
-    loop {
-        let mut fragment = vec![0; fragment_size];
-        f_in.read(&mut fragment[..])
+    let mut fragment = vec![0; fragment_size];
+    while remaining > 0 {
+        let len = remaining.min(fragment_size as u64) as usize;
+        f_in.read_exact(&mut fragment[..len])
             .map_err(|err| ScalpelError::ReadingError.context(err))?;
 
-        if remaining < fragment_size as u64 {
-            f_out
-                .write_all(&fragment[0..(remaining as usize)])
-                .map_err(|err| ScalpelError::WritingError.context(err))?;
-
-            return Ok(());
-        } else {
-            f_out
-                .write_all(&fragment[..])
-                .map_err(|err| ScalpelError::WritingError.context(err))?;
-            remaining -= fragment_size as u64;
-        }
+        f_out
+            .write_all(&fragment[..len])
+            .map_err(|err| ScalpelError::WritingError.context(err))?;
+        remaining -= len as u64;
     }
+    Ok(())
+}
+
+/// cut `size` bytes at `start` out of an image, the result keeps its load address
+pub fn cut_image(mut image: Image, start: u64, size: u64) -> Result<Image> {
+    let len = image.bytes.len() as u64;
+    let end = match start.checked_add(size) {
+        Some(end) if end <= len => end,
+        _ => {
+            return Err(ScalpelError::ArgumentError
+                .context(format!(
+                    "Section of {} bytes at {} exceeds the length {} of the input",
+                    size, start, len
+                ))
+                .into())
+        }
+    };
+    image.bytes.truncate(end as usize);
+    let bytes = image.bytes.split_off(start as usize);
+
+    Ok(Image {
+        address: image.address + start,
+        bytes,
+        start_address: image.start_address,
+    })
 }
 
 
@@ -68,7 +87,7 @@ mod test {
                 .open(victim.clone())
                 .expect("Failed to open file");
             file_tester
-                .write_all(&bytes)
+                .write_all(bytes)
                 .expect("Failed to write to file");
         }
         // cut bytes from this file
@@ -82,11 +101,56 @@ mod test {
             .open(output)
             .expect("Failed to open ouput file");
         file_tested
-            .read(&mut output_bytes)
+            .read_exact(&mut output_bytes)
             .expect("Failed to read file");
 
         println!("{:?}", output_bytes);
         assert_eq!(output_bytes, &bytes[5..9]);
     }
 
+    #[test]
+    fn test_cut_image() {
+        let image = Image {
+            address: 0x1000,
+            bytes: (0u8..16).collect(),
+            start_address: None,
+        };
+        let cut = cut_image(image, 4, 8).expect("Failed to cut image");
+        assert_eq!(cut.address, 0x1004);
+        assert_eq!(&cut.bytes[..], &[4, 5, 6, 7, 8, 9, 10, 11]);
+
+        let image = Image {
+            bytes: (0u8..16).collect(),
+            ..Image::default()
+        };
+        assert!(cut_image(image.clone(), 12, 8).is_err());
+        assert!(cut_image(image, 0xFFFF_FFFF_FFFF_FFF0, 0x20).is_err());
+    }
+
+    #[test]
+    fn test_cut_signature() {
+        use byte_offset::ByteOffset;
+        use signer::Signer;
+        use bytes::{Bytes, BytesMut};
+
+        // a signed image as written by concat::append_signature
+        let content: Bytes = (0..1000u32).map(|i| i as u8).collect();
+        let signature = Signer::random()
+            .calculate_signature(&content)
+            .expect("Failed to sign");
+        let mut bytes = BytesMut::from(&content[..]);
+        bytes.extend_from_slice(signature.as_ref());
+        let image = Image {
+            bytes,
+            ..Image::default()
+        };
+
+        let start = ByteOffset::parse("-64")
+            .expect("Failed to parse offset")
+            .resolve(Some(image.bytes.len() as u64), None)
+            .expect("Failed to resolve offset");
+        let cut = cut_image(image, start, 64).expect("Failed to cut signature");
+        assert_eq!(&cut.bytes[..], &signature[..]);
+    }
+
 }
@@ -0,0 +1,596 @@
+use bytes::BytesMut;
+use std::path::Path;
+
+use errors::*;
+use format::{Chunks, Image, StartAddress};
+use stitch::FillPattern;
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHT_DYNSYM: u32 = 11;
+
+/// Byte order of all multi byte fields of an ELF file
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Endian {
+    Little,
+    Big,
+}
+
+/// Bounds checked access to the fields of an ELF file
+struct Reader<'a> {
+    bytes: &'a [u8],
+    endian: Endian,
+    is_64: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn uint(&self, offset: u64, width: usize) -> Result<u64> {
+        let start = offset as usize;
+        let field = start
+            .checked_add(width)
+            .and_then(|end| self.bytes.get(start..end))
+            .ok_or_else(|| ScalpelError::ElfError {
+                r: format!("field at {:#X} is out of bounds", offset),
+            })?;
+        Ok(match self.endian {
+            Endian::Little => field
+                .iter()
+                .rev()
+                .fold(0u64, |acc, byte| acc << 8 | u64::from(*byte)),
+            Endian::Big => field
+                .iter()
+                .fold(0u64, |acc, byte| acc << 8 | u64::from(*byte)),
+        })
+    }
+
+    fn u16(&self, offset: u64) -> Result<u64> {
+        self.uint(offset, 2)
+    }
+
+    fn u32(&self, offset: u64) -> Result<u64> {
+        self.uint(offset, 4)
+    }
+
+    /// a field which is 32 bit wide in ELF32 and 64 bit wide in ELF64
+    fn word(&self, offset: u64) -> Result<u64> {
+        self.uint(offset, if self.is_64 { 8 } else { 4 })
+    }
+
+    /// offset of entry `index` of the table at `table`, which has to start within the file
+    fn entry(&self, table: u64, index: u64, entsize: u64) -> Result<u64> {
+        index
+            .checked_mul(entsize)
+            .and_then(|relative| table.checked_add(relative))
+            .filter(|&entry| entry < self.bytes.len() as u64)
+            .ok_or_else(|| {
+                ScalpelError::ElfError {
+                    r: format!("entry {} of the table at {:#X} is out of bounds", index, table),
+                }
+                .into()
+            })
+    }
+}
+
+/// Entry of the section header table
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    /// index of the associated string table for symbol tables
+    pub link: u32,
+}
+
+/// Entry of the program header table
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub kind: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+}
+
+/// An ELF32 or ELF64 file of either endianness
+#[derive(Debug)]
+pub struct Elf {
+    bytes: Vec<u8>,
+    endian: Endian,
+    is_64: bool,
+    pub entry: u64,
+    pub sections: Vec<Section>,
+    pub segments: Vec<Segment>,
+}
+
+impl Elf {
+    pub fn parse(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.len() < 16 || &bytes[0..4] != b"\x7FELF" {
+            return Err(ScalpelError::ElfError {
+                r: "missing ELF magic".to_string(),
+            }
+            .into());
+        }
+        let is_64 = match bytes[4] {
+            1 => false,
+            2 => true,
+            class => {
+                return Err(ScalpelError::ElfError {
+                    r: format!("unknown class {}", class),
+                }
+                .into())
+            }
+        };
+        let endian = match bytes[5] {
+            1 => Endian::Little,
+            2 => Endian::Big,
+            data => {
+                return Err(ScalpelError::ElfError {
+                    r: format!("unknown data encoding {}", data),
+                }
+                .into())
+            }
+        };
+
+        let (entry, phoff, shoff, fields) = {
+            let r = Reader {
+                bytes: &bytes,
+                endian,
+                is_64,
+            };
+            // e_phentsize, e_phnum, e_shentsize, e_shnum and e_shstrndx follow
+            // e_entry, e_phoff, e_shoff, e_flags and e_ehsize
+            let w = if is_64 { 8 } else { 4 };
+            let fields = 24 + 3 * w + 6;
+            (r.word(24)?, r.word(24 + w)?, r.word(24 + 2 * w)?, fields)
+        };
+
+        let mut elf = Elf {
+            bytes,
+            endian,
+            is_64,
+            entry,
+            sections: Vec::new(),
+            segments: Vec::new(),
+        };
+        elf.segments = elf.parse_segments(phoff, fields)?;
+        elf.sections = elf.parse_sections(shoff, fields + 4)?;
+        Ok(elf)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let bytes = ::stitch::read_file(path)?;
+        Self::parse(bytes.to_vec())
+            .map_err(|e| ScalpelError::ContentError.context(format!("{:?}: {}", path, e)).into())
+    }
+
+    fn reader(&self) -> Reader<'_> {
+        Reader {
+            bytes: &self.bytes,
+            endian: self.endian,
+            is_64: self.is_64,
+        }
+    }
+
+    /// `fields` is the offset of e_phentsize and e_phnum in the header
+    fn parse_segments(&self, phoff: u64, fields: u64) -> Result<Vec<Segment>> {
+        let r = self.reader();
+        let entsize = r.u16(fields)?;
+        let num = r.u16(fields + 2)?;
+
+        (0..num)
+            .map(|i| {
+                let ph = r.entry(phoff, i, entsize)?;
+                Ok(if self.is_64 {
+                    Segment {
+                        kind: r.u32(ph)? as u32,
+                        offset: r.word(ph + 8)?,
+                        vaddr: r.word(ph + 16)?,
+                        paddr: r.word(ph + 24)?,
+                        filesz: r.word(ph + 32)?,
+                    }
+                } else {
+                    Segment {
+                        kind: r.u32(ph)? as u32,
+                        offset: r.word(ph + 4)?,
+                        vaddr: r.word(ph + 8)?,
+                        paddr: r.word(ph + 12)?,
+                        filesz: r.word(ph + 16)?,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// `fields` is the offset of e_shentsize, e_shnum and e_shstrndx in the header
+    fn parse_sections(&self, shoff: u64, fields: u64) -> Result<Vec<Section>> {
+        let r = self.reader();
+        let entsize = r.u16(fields)?;
+        let num = r.u16(fields + 2)?;
+        let shstrndx = r.u16(fields + 4)?;
+
+        // names are resolved once the string table section is known
+        let mut sections = (0..num)
+            .map(|i| {
+                let sh = r.entry(shoff, i, entsize)?;
+                let w = if self.is_64 { 8 } else { 4 };
+                Ok((
+                    r.u32(sh)?,
+                    Section {
+                        name: String::new(),
+                        kind: r.u32(sh + 4)? as u32,
+                        addr: r.word(sh + 8 + w)?,
+                        offset: r.word(sh + 8 + 2 * w)?,
+                        size: r.word(sh + 8 + 3 * w)?,
+                        link: r.u32(sh + 8 + 4 * w)? as u32,
+                    },
+                ))
+            })
+            .collect::<Result<Vec<(u64, Section)>>>()?;
+
+        if let Some(strtab) = sections.get(shstrndx as usize).map(|s| s.1.clone()) {
+            for &mut (name, ref mut section) in sections.iter_mut() {
+                section.name = self.string(&strtab, name)?;
+            }
+        }
+        Ok(sections.into_iter().map(|(_, section)| section).collect())
+    }
+
+    /// null terminated string at `index` of the string table `strtab`
+    fn string(&self, strtab: &Section, index: u64) -> Result<String> {
+        let table = self.content(strtab)?;
+        let tail = table.get(index as usize..).ok_or_else(|| ScalpelError::ElfError {
+            r: format!("string index {} is out of bounds", index),
+        })?;
+        let end = tail.iter().position(|&b| b == 0).unwrap_or(tail.len());
+        Ok(String::from_utf8_lossy(&tail[..end]).into_owned())
+    }
+
+    /// file bytes of a section
+    fn content(&self, section: &Section) -> Result<&[u8]> {
+        self.file_bytes(section.offset, section.size)
+    }
+
+    fn file_bytes(&self, offset: u64, size: u64) -> Result<&[u8]> {
+        offset
+            .checked_add(size)
+            .and_then(|end| self.bytes.get(offset as usize..end as usize))
+            .ok_or_else(|| {
+                ScalpelError::ElfError {
+                    r: format!("{} bytes at {:#X} exceed the file", size, offset),
+                }
+                .into()
+            })
+    }
+
+    fn start_address(&self) -> Option<StartAddress> {
+        if self.entry != 0 && self.entry <= u64::from(u32::MAX) {
+            Some(StartAddress::Linear(self.entry as u32))
+        } else {
+            None
+        }
+    }
+
+    /// load address of the byte at file `offset`, if it belongs to a loadable segment
+    fn load_address(&self, offset: u64) -> Option<u64> {
+        self.segments
+            .iter()
+            .find(|seg| seg.kind == PT_LOAD && seg.offset <= offset && offset - seg.offset < seg.filesz)
+            .and_then(|seg| seg.paddr.checked_add(offset - seg.offset))
+    }
+
+    /// value of the defined symbol `name`, taken from the symbol table or the dynamic one
+    pub fn symbol(&self, name: &str) -> Result<u64> {
+        let r = self.reader();
+        let entsize = if self.is_64 { 24 } else { 16 };
+
+        for table in self
+            .sections
+            .iter()
+            .filter(|section| section.kind == SHT_SYMTAB || section.kind == SHT_DYNSYM)
+        {
+            let strtab = self.sections.get(table.link as usize).ok_or_else(|| ScalpelError::ElfError {
+                r: format!("symbol table {} links to a missing string table", table.name),
+            })?;
+            for i in 0..table.size / entsize {
+                let sym = r.entry(table.offset, i, entsize)?;
+                let (value, shndx) = if self.is_64 {
+                    (r.word(sym + 8)?, r.u16(sym + 6)?)
+                } else {
+                    (r.word(sym + 4)?, r.u16(sym + 14)?)
+                };
+                // undefined symbols have no value
+                if shndx != 0 && self.string(strtab, r.u32(sym)?)? == name {
+                    return Ok(value);
+                }
+            }
+        }
+
+        Err(ScalpelError::ElfError {
+            r: format!("no symbol named {}", name),
+        }
+        .into())
+    }
+
+    /// file offset of the virtual address `vaddr`, using the loadable segments
+    pub fn vaddr_to_offset(&self, vaddr: u64) -> Result<u64> {
+        self.segments
+            .iter()
+            .find(|seg| seg.kind == PT_LOAD && seg.vaddr <= vaddr && vaddr - seg.vaddr < seg.filesz)
+            .and_then(|seg| seg.offset.checked_add(vaddr - seg.vaddr))
+            .ok_or_else(|| {
+                ScalpelError::ElfError {
+                    r: format!("address {:#X} is not backed by the file", vaddr),
+                }
+                .into()
+            })
+    }
+
+    /// file offset of the symbol `name`
+    pub fn symbol_offset(&self, name: &str) -> Result<u64> {
+        self.vaddr_to_offset(self.symbol(name)?)
+    }
+
+    /// file bytes of the section `name`, located at its load address
+    pub fn section_image(&self, name: &str) -> Result<Image> {
+        let section = self
+            .sections
+            .iter()
+            .find(|section| section.name == name)
+            .ok_or_else(|| ScalpelError::ElfError {
+                r: format!("no section named {}", name),
+            })?;
+        if section.kind == SHT_NOBITS {
+            return Err(ScalpelError::ElfError {
+                r: format!("section {} occupies no space in the file", name),
+            }
+            .into());
+        }
+
+        Ok(Image {
+            address: self.load_address(section.offset).unwrap_or(section.addr),
+            bytes: BytesMut::from(self.content(section)?),
+            start_address: self.start_address(),
+        })
+    }
+
+    /// file bytes of the loadable segment with program header `index`
+    pub fn segment_image(&self, index: usize) -> Result<Image> {
+        let segment = self.segments.get(index).ok_or_else(|| ScalpelError::ElfError {
+            r: format!("no segment {}, there are only {}", index, self.segments.len()),
+        })?;
+        if segment.kind != PT_LOAD {
+            return Err(ScalpelError::ElfError {
+                r: format!("segment {} is not loadable", index),
+            }
+            .into());
+        }
+
+        Ok(Image {
+            address: segment.paddr,
+            bytes: BytesMut::from(self.file_bytes(segment.offset, segment.filesz)?),
+            start_address: self.start_address(),
+        })
+    }
+
+    /// raw image of all loadable segments, gaps are filled with `fill_pattern`
+    pub fn flatten(&self, fill_pattern: &FillPattern) -> Result<Image> {
+        let mut chunks = Chunks::default();
+        for segment in self.segments.iter().filter(|seg| seg.kind == PT_LOAD && seg.filesz > 0) {
+            chunks.push(segment.paddr, self.file_bytes(segment.offset, segment.filesz)?);
+        }
+        chunks.start_address = self.start_address();
+        chunks.flatten(fill_pattern)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// in memory ELF file builder, so all classes and byte orders can be tested
+    pub struct Builder {
+        pub is_64: bool,
+        pub big_endian: bool,
+        pub bytes: Vec<u8>,
+    }
+
+    impl Builder {
+        fn put(&mut self, offset: usize, value: u64, width: usize) {
+            if self.bytes.len() < offset + width {
+                self.bytes.resize(offset + width, 0);
+            }
+            for i in 0..width {
+                let shift = if self.big_endian { 8 * (width - 1 - i) } else { 8 * i };
+                self.bytes[offset + i] = (value >> shift) as u8;
+            }
+        }
+
+        fn word(&self) -> usize {
+            if self.is_64 {
+                8
+            } else {
+                4
+            }
+        }
+
+        fn put_word(&mut self, offset: usize, value: u64) {
+            let w = self.word();
+            self.put(offset, value, w);
+        }
+
+        fn put_bytes(&mut self, offset: usize, bytes: &[u8]) {
+            if self.bytes.len() < offset + bytes.len() {
+                self.bytes.resize(offset + bytes.len(), 0);
+            }
+            self.bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+
+        /// a firmware with `.text` at 0x1000 and `.data` loaded at 0x1010 but linked to 0x2000
+        pub fn firmware(is_64: bool, big_endian: bool) -> Self {
+            let mut b = Builder {
+                is_64,
+                big_endian,
+                bytes: Vec::new(),
+            };
+            let w = b.word();
+            b.put_bytes(0, b"\x7FELF");
+            b.put(4, if is_64 { 2 } else { 1 }, 1);
+            b.put(5, if big_endian { 2 } else { 1 }, 1);
+            b.put(6, 1, 1);
+
+            let ehsize = 24 + 3 * w + 16;
+            let phentsize = if is_64 { 56 } else { 32 };
+            let shentsize = if is_64 { 64 } else { 40 };
+            let phoff = ehsize;
+            let shoff = 0x200;
+            b.put_word(24, 0x1001);
+            b.put_word(24 + w, phoff as u64);
+            b.put_word(24 + 2 * w, shoff as u64);
+            let sizes = 24 + 3 * w + 4;
+            b.put(sizes, ehsize as u64, 2);
+            b.put(sizes + 2, phentsize as u64, 2);
+            b.put(sizes + 4, 2, 2);
+            b.put(sizes + 6, shentsize as u64, 2);
+            b.put(sizes + 8, 7, 2);
+            b.put(sizes + 10, 4, 2);
+
+            // .text and .data contents
+            b.put_bytes(0x100, &[1, 2, 3, 4, 5, 6, 7, 8]);
+            b.put_bytes(0x108, &[9, 10, 11, 12]);
+            let segments = [(0x100u64, 0x1000u64, 0x1000u64, 8u64, 8u64), (0x108, 0x2000, 0x1010, 4, 16)];
+            for (i, &(offset, vaddr, paddr, filesz, memsz)) in segments.iter().enumerate() {
+                let ph = phoff + i * phentsize;
+                b.put(ph, u64::from(PT_LOAD), 4);
+                let fields = if is_64 { ph + 8 } else { ph + 4 };
+                for (j, value) in [offset, vaddr, paddr, filesz, memsz].iter().enumerate() {
+                    b.put_word(fields + j * w, *value);
+                }
+            }
+
+            let shstrtab = b"\0.text\0.data\0.bss\0.shstrtab\0.symtab\0.strtab\0";
+            b.put_bytes(0x180, shstrtab);
+
+            // null symbol, app_header in .text and counter in .data
+            let strtab = b"\0app_header\0counter\0";
+            b.put_bytes(0x580, strtab);
+            let symentsize = if is_64 { 24 } else { 16 };
+            let symbols = [(0u64, 0u64, 0u64), (1, 0x1004, 1), (12, 0x2001, 2)];
+            for (i, &(name, value, shndx)) in symbols.iter().enumerate() {
+                let sym = 0x500 + i * symentsize;
+                b.put(sym, name, 4);
+                if is_64 {
+                    b.put(sym + 6, shndx, 2);
+                    b.put(sym + 8, value, 8);
+                } else {
+                    b.put(sym + 4, value, 4);
+                    b.put(sym + 14, shndx, 2);
+                }
+            }
+
+            let sections = [
+                (0u64, 0u64, 0u64, 0u64, 0u64),
+                (1, 1, 0x1000, 0x100, 8),
+                (7, 1, 0x2000, 0x108, 4),
+                (13, u64::from(SHT_NOBITS), 0x2004, 0x10C, 12),
+                (18, 3, 0, 0x180, shstrtab.len() as u64),
+                (28, u64::from(SHT_SYMTAB), 0, 0x500, (symbols.len() * symentsize) as u64),
+                (36, 3, 0, 0x580, strtab.len() as u64),
+            ];
+            for (i, &(name, kind, addr, offset, size)) in sections.iter().enumerate() {
+                let sh = shoff + i * shentsize;
+                b.put(sh, name, 4);
+                b.put(sh + 4, kind, 4);
+                b.put_word(sh + 8 + w, addr);
+                b.put_word(sh + 8 + 2 * w, offset);
+                b.put_word(sh + 8 + 3 * w, size);
+                if kind == u64::from(SHT_SYMTAB) {
+                    b.put(sh + 8 + 4 * w, 6, 4);
+                }
+            }
+            let end = (shoff + sections.len() * shentsize).max(b.bytes.len());
+            b.bytes.resize(end, 0);
+            b
+        }
+    }
+
+    #[test]
+    fn all_classes_and_byte_orders() {
+        for &(is_64, big_endian) in &[(false, false), (false, true), (true, false), (true, true)] {
+            let elf = Elf::parse(Builder::firmware(is_64, big_endian).bytes)
+                .expect("Failed to parse ELF");
+            assert_eq!(elf.entry, 0x1001);
+            assert_eq!(elf.segments.len(), 2);
+            assert_eq!(elf.sections[3].name, ".bss");
+
+            let text = elf.section_image(".text").expect("Failed to extract .text");
+            assert_eq!(text.address, 0x1000);
+            assert_eq!(&text.bytes[..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+            // .data is located at its load address, not its link address
+            let data = elf.section_image(".data").expect("Failed to extract .data");
+            assert_eq!(data.address, 0x1010);
+            assert_eq!(&data.bytes[..], &[9, 10, 11, 12]);
+
+            assert!(elf.section_image(".bss").is_err());
+            assert!(elf.section_image(".nope").is_err());
+
+            let segment = elf.segment_image(1).expect("Failed to extract segment");
+            assert_eq!(segment.address, 0x1010);
+            assert_eq!(&segment.bytes[..], &[9, 10, 11, 12]);
+            assert!(elf.segment_image(2).is_err());
+
+            let flat = elf.flatten(&FillPattern::One).expect("Failed to flatten");
+            assert_eq!(flat.address, 0x1000);
+            assert_eq!(
+                &flat.bytes[..],
+                &[1, 2, 3, 4, 5, 6, 7, 8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 9, 10, 11, 12]
+            );
+            assert_eq!(flat.start_address, Some(StartAddress::Linear(0x1001)));
+        }
+    }
+
+    #[test]
+    fn symbols() {
+        for &(is_64, big_endian) in &[(false, false), (true, true)] {
+            let elf = Elf::parse(Builder::firmware(is_64, big_endian).bytes)
+                .expect("Failed to parse ELF");
+            assert_eq!(elf.symbol("app_header").unwrap(), 0x1004);
+            assert_eq!(elf.symbol_offset("app_header").unwrap(), 0x104);
+            assert_eq!(elf.symbol_offset("counter").unwrap(), 0x109);
+            assert!(elf.symbol("missing").is_err());
+            assert!(elf.vaddr_to_offset(0x3000).is_err());
+        }
+    }
+
+    #[test]
+    fn reject_garbage() {
+        assert!(Elf::parse(vec![0x7F, b'E', b'L', b'F']).is_err());
+        assert!(Elf::parse(b"MZ\x90\x00 definitely not an elf".to_vec()).is_err());
+    }
+
+    #[test]
+    fn reject_malformed_header() {
+        // program and section header tables beyond the end of the address space
+        let mut b = Builder::firmware(true, false);
+        b.put_word(32, 0xFFFF_FFFF_FFFF_FFFC);
+        assert!(Elf::parse(b.bytes).is_err());
+        let mut b = Builder::firmware(true, false);
+        b.put_word(40, 0xFFFF_FFFF_FFFF_FFF0);
+        assert!(Elf::parse(b.bytes).is_err());
+
+        // segment and symbol table contents beyond the end of the address space
+        let mut b = Builder::firmware(true, false);
+        b.put_word(64 + 56 + 8, 0xFFFF_FFFF_FFFF_FFF0);
+        b.put_word(64 + 56 + 32, 0x20);
+        let elf = Elf::parse(b.bytes).expect("Failed to parse ELF");
+        assert!(elf.segment_image(1).is_err());
+        assert!(elf.flatten(&FillPattern::One).is_err());
+        let mut b = Builder::firmware(true, false);
+        b.put_word(0x200 + 5 * 64 + 24, 0xFFFF_FFFF_FFFF_FFF0);
+        let elf = Elf::parse(b.bytes).expect("Failed to parse ELF");
+        assert!(elf.symbol("counter").is_err());
+    }
+}
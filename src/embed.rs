@@ -0,0 +1,169 @@
+use bytes::{Bytes, BytesMut};
+use std::path::Path;
+
+use byte_offset::{ByteOffset, ByteRange};
+use elf::Elf;
+use errors::*;
+use replace;
+use signer::{Signer, Verifier};
+use stitch::{read_file, write_file, FillPattern};
+
+/// Bytes covered by a signature and the slot within the same image the signature is embedded in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub start: u64,
+    pub end: u64,
+    pub slot: u64,
+    pub slot_len: u64,
+}
+
+impl Region {
+    /// the range `start..end` of an image of `len` bytes with an optional slot of `slot_len` bytes at `slot`
+    pub fn new(start: u64, end: u64, slot: Option<u64>, slot_len: usize, len: u64) -> Result<Self> {
+        let region = Region {
+            start,
+            end,
+            slot: slot.unwrap_or(end),
+            slot_len: if slot.is_some() { slot_len as u64 } else { 0 },
+        };
+        let slot_end = region.slot.checked_add(region.slot_len);
+        if region.start > region.end || region.end > len || slot_end.is_none_or(|slot_end| slot_end > len) {
+            return Err(ScalpelError::ArgumentError
+                .context(format!(
+                    "Range {}..{} or slot of {} bytes at {} exceeds the length {} of the input",
+                    region.start,
+                    region.end,
+                    region.slot_len,
+                    region.slot,
+                    len
+                ))
+                .into());
+        }
+        Ok(region)
+    }
+
+    /// resolve `range` and `slot` with `end` being the length `len` of the image
+    pub fn resolve(range: &ByteRange, slot: &ByteOffset, slot_len: usize, len: u64, symbols: Option<&Elf>) -> Result<Self> {
+        let (start, end) = range.resolve(Some(len), symbols)?;
+        Region::new(start, end, Some(slot.resolve(Some(len), symbols)?), slot_len, len)
+    }
+
+    /// the bytes stored in the slot
+    pub fn slot_bytes<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        &bytes[self.slot as usize..(self.slot + self.slot_len) as usize]
+    }
+
+    /// the bytes of the range without the slot, as the signature can not cover itself
+    pub fn message(&self, bytes: &[u8]) -> Bytes {
+        let slot_end = self.slot + self.slot_len;
+        let mut message = BytesMut::with_capacity((self.end - self.start) as usize);
+        if self.slot >= self.end || slot_end <= self.start {
+            message.extend_from_slice(&bytes[self.start as usize..self.end as usize]);
+        } else {
+            message.extend_from_slice(&bytes[self.start as usize..self.slot.max(self.start) as usize]);
+            message.extend_from_slice(&bytes[slot_end.min(self.end) as usize..self.end as usize]);
+        }
+        message.freeze()
+    }
+}
+
+/// sign `range` of the file at `path` and write it with the signature in the slot at `slot` to `output`,
+/// which may be the file itself
+pub fn sign_region(
+    signer: &Signer,
+    path: &Path,
+    output: &Path,
+    range: &ByteRange,
+    slot: &ByteOffset,
+    symbols: Option<&Elf>,
+) -> Result<Region> {
+    let bytes = read_file(path)?;
+    let slot_len = signer.verifier()?.signature_len();
+    let region = Region::resolve(range, slot, slot_len, bytes.len() as u64, symbols)?;
+
+    let signature = signer.calculate_signature(&region.message(&bytes))?;
+    write_slot(bytes, region.slot, &signature, output)?;
+
+    Ok(region)
+}
+
+/// write `bytes` with `content` in the slot at `slot` to `output`, like `replace` does
+pub fn write_slot(bytes: BytesMut, slot: u64, content: &[u8], output: &Path) -> Result<()> {
+    let bytes = replace::replace(
+        BytesMut::from(content),
+        bytes,
+        slot as usize,
+        content.len(),
+        FillPattern::Zero,
+    )?;
+    write_file(output, &bytes)
+}
+
+/// verify the signature embedded in the slot at `slot` of the file at `path` against `range`
+pub fn verify_region(verifier: &Verifier, path: &Path, range: &ByteRange, slot: &ByteOffset, symbols: Option<&Elf>) -> Result<()> {
+    let bytes = read_file(path)?;
+    let region = Region::resolve(range, slot, verifier.signature_len(), bytes.len() as u64, symbols)?;
+
+    verifier
+        .verify(&region.message(&bytes), region.slot_bytes(&bytes))
+        .map_err(|e| ScalpelError::ContentError.context(e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use byte_offset::Magnitude;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use testing::TempFile;
+
+    #[test]
+    fn region_without_slot() {
+        let bytes: Vec<u8> = (0..100).collect();
+        let region = |start, end, slot| Region { start, end, slot, slot_len: 10 };
+        // slot in front of, within, overlapping and behind the range
+        assert_eq!(region(20, 40, 0).message(&bytes)[..], bytes[20..40]);
+        assert_eq!(region(20, 40, 25).message(&bytes)[..], [&bytes[20..25], &bytes[35..40]].concat()[..]);
+        assert_eq!(region(20, 40, 15).message(&bytes)[..], bytes[25..40]);
+        assert_eq!(region(20, 40, 35).message(&bytes)[..], bytes[20..35]);
+        assert_eq!(region(20, 40, 40).message(&bytes)[..], bytes[20..40]);
+
+        let range = ByteRange::parse("0..end").unwrap();
+        assert!(Region::resolve(&range, &ByteOffset::new(95, Magnitude::Unit), 10, 100, None).is_err());
+        assert!(Region::resolve(&ByteRange::parse("0..101").unwrap(), &ByteOffset::default(), 10, 100, None).is_err());
+        assert!(Region::new(0, 100, Some(u64::MAX), 10, 100).is_err());
+    }
+
+    #[test]
+    fn sign_in_place() {
+        let image = TempFile::copy("tmp/test_bytes", "tmp/test_region.bin");
+        let path = image.path();
+        let original = read_file(path).expect("Failed to read file");
+
+        let range = ByteRange::parse("0..end").unwrap();
+        let slot = ByteOffset::parse("0x100").unwrap();
+        for key in &["tmp/rsa2048_keypair.pk8", "tmp/p256_keypair.pk8"] {
+            let signer = Signer::from_pkcs8_file(Path::new(key)).expect("Failed to read keys");
+            let verifier = signer.verifier().expect("Failed to get verifier");
+            let region = sign_region(&signer, path, path, &range, &slot, None).expect("Signing failed");
+
+            // only the slot changes
+            let signed = read_file(path).expect("Failed to read signed file");
+            assert_eq!(signed.len(), original.len());
+            assert_eq!(signed[..0x100], original[..0x100]);
+            assert_eq!(signed[(0x100 + region.slot_len) as usize..], original[(0x100 + region.slot_len) as usize..]);
+            assert!(verify_region(&verifier, path, &range, &slot, None).is_ok());
+
+            // bytes outside of the range are not covered
+            let smaller = ByteRange::parse("0..end-16").unwrap();
+            sign_region(&signer, path, path, &smaller, &slot, None).expect("Signing failed");
+            let mut file = OpenOptions::new().append(true).open(path).expect("Failed to open file");
+            file.write_all(&[0x42]).expect("Failed to write");
+            let tampered = ByteRange::parse("0..end-17").unwrap();
+            assert!(verify_region(&verifier, path, &tampered, &slot, None).is_ok());
+            assert!(verify_region(&verifier, path, &range, &slot, None).is_err());
+            ::std::fs::copy("tmp/test_bytes", path).expect("Failed to copy test bytes");
+        }
+    }
+}
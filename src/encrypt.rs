@@ -0,0 +1,288 @@
+use ring::aead;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::path::Path;
+
+use errors::*;
+use stitch::{read_file, write_file};
+use trailer::{Keyring, Trailer};
+
+/// Encrypted images get a trailer of magic, version, cipher, start and end (u64 LE) of the encrypted range,
+/// nonce, tag and finally the length of the whole trailer (u32 LE), like signature trailers.
+/// The fields up to the end of the range are authenticated as well, the bytes outside of the range are not,
+/// so a clear header may still be signed in place.
+const MAGIC: &[u8] = b"SCPE";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 4 + 1 + 1 + 8 + 8;
+const TRAILER_LEN: usize = HEADER_LEN + aead::NONCE_LEN + aead::MAX_TAG_LEN + 4;
+
+/// Authenticated ciphers to encrypt images with
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Cipher {
+    Aes128Gcm,
+    #[default]
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn algorithm(self) -> &'static aead::Algorithm {
+        match self {
+            Cipher::Aes128Gcm => &aead::AES_128_GCM,
+            Cipher::Aes256Gcm => &aead::AES_256_GCM,
+            Cipher::ChaCha20Poly1305 => &aead::CHACHA20_POLY1305,
+        }
+    }
+
+    /// identifier within the trailer
+    fn id(self) -> u8 {
+        match self {
+            Cipher::Aes128Gcm => 1,
+            Cipher::Aes256Gcm => 2,
+            Cipher::ChaCha20Poly1305 => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Cipher::Aes128Gcm),
+            2 => Some(Cipher::Aes256Gcm),
+            3 => Some(Cipher::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    /// number of bytes of a key
+    pub fn key_len(self) -> usize {
+        self.algorithm().key_len()
+    }
+
+    fn header(self, start: u64, end: u64) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.push(self.id());
+        header.extend_from_slice(&start.to_le_bytes());
+        header.extend_from_slice(&end.to_le_bytes());
+        header
+    }
+
+    /// encrypt `start..end` of `bytes` in place with `nonce` and append the trailer
+    fn seal(self, key: &[u8], nonce: [u8; aead::NONCE_LEN], bytes: &mut Vec<u8>, start: u64, end: u64) -> Result<()> {
+        if start > end || end > bytes.len() as u64 {
+            return Err(ScalpelError::ArgumentError
+                .context(format!("Range {}..{} exceeds the length {} of the input", start, end, bytes.len()))
+                .into());
+        }
+        let key = aead::SealingKey::new(self.algorithm(), key)
+            .map_err(|_| ScalpelError::KeyError { r: format!("invalid {:?} key", self) })?;
+        let header = self.header(start, end);
+
+        let mut in_out = bytes[start as usize..end as usize].to_vec();
+        in_out.extend_from_slice(&[0; aead::MAX_TAG_LEN]);
+        aead::seal_in_place(
+            &key,
+            aead::Nonce::assume_unique_for_key(nonce),
+            aead::Aad::from(&header),
+            &mut in_out,
+            aead::MAX_TAG_LEN,
+        )
+        .map_err(|_| ScalpelError::EncryptionError { r: "sealing failed".to_string() })?;
+        let (ciphertext, tag) = in_out.split_at(in_out.len() - aead::MAX_TAG_LEN);
+        bytes[start as usize..end as usize].copy_from_slice(ciphertext);
+
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(tag);
+        bytes.extend_from_slice(&(TRAILER_LEN as u32).to_le_bytes());
+        Ok(())
+    }
+}
+
+/// read a raw key of `cipher` from `path`
+pub fn read_key(path: &Path, cipher: Cipher) -> Result<Vec<u8>> {
+    let key = read_file(path)?.to_vec();
+    if key.len() != cipher.key_len() {
+        return Err(ScalpelError::KeyError {
+            r: format!("{:?} has {} bytes, {:?} needs a key of {}", path, key.len(), cipher, cipher.key_len()),
+        }
+        .into());
+    }
+    Ok(key)
+}
+
+/// encrypt `start..end` of `bytes` with a random nonce, the trailer is appended
+pub fn encrypt(cipher: Cipher, key: &[u8], mut bytes: Vec<u8>, start: u64, end: u64) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| ScalpelError::EncryptionError { r: "no random nonce".to_string() })?;
+    cipher.seal(key, nonce, &mut bytes, start, end)?;
+    Ok(bytes)
+}
+
+/// decrypt an encrypted image, signatures appended after encryption have to be verified and removed before
+///
+/// `key` is the raw key file content, the cipher is given by the trailer.
+pub fn decrypt(key: &[u8], content: &[u8]) -> Result<Vec<u8>> {
+    if Trailer::parse(content)?.is_some() {
+        return Err(ScalpelError::ArgumentError
+            .context("Signature trailers have to be verified with --pubkey or --keyring")
+            .into());
+    }
+    let malformed = |r: String| -> Error { ScalpelError::EncryptionTrailerError { r }.into() };
+    if content.len() < TRAILER_LEN || !content[content.len() - TRAILER_LEN..].starts_with(MAGIC) {
+        return Err(malformed("no encryption trailer".to_string()));
+    }
+
+    let (data, trailer) = content.split_at(content.len() - TRAILER_LEN);
+    if trailer[4] != VERSION {
+        return Err(malformed(format!("unsupported version {}", trailer[4])));
+    }
+    let cipher = Cipher::from_id(trailer[5]).ok_or_else(|| malformed(format!("unknown cipher {}", trailer[5])))?;
+    let mut range = [0u8; 8];
+    range.copy_from_slice(&trailer[6..14]);
+    let start = u64::from_le_bytes(range);
+    range.copy_from_slice(&trailer[14..HEADER_LEN]);
+    let end = u64::from_le_bytes(range);
+    if start > end || end > data.len() as u64 {
+        return Err(malformed(format!("range {}..{} exceeds the {} encrypted bytes", start, end, data.len())));
+    }
+    if key.len() != cipher.key_len() {
+        return Err(ScalpelError::KeyError {
+            r: format!("key has {} bytes, {:?} needs a key of {}", key.len(), cipher, cipher.key_len()),
+        }
+        .into());
+    }
+
+    let key = aead::OpeningKey::new(cipher.algorithm(), key)
+        .map_err(|_| ScalpelError::KeyError { r: format!("invalid {:?} key", cipher) })?;
+    let nonce = aead::Nonce::try_assume_unique_for_key(&trailer[HEADER_LEN..HEADER_LEN + aead::NONCE_LEN])
+        .map_err(|_| malformed("invalid nonce".to_string()))?;
+    let mut in_out = data[start as usize..end as usize].to_vec();
+    in_out.extend_from_slice(&trailer[HEADER_LEN + aead::NONCE_LEN..TRAILER_LEN - 4]);
+    let plaintext = aead::open_in_place(&key, nonce, aead::Aad::from(&trailer[..HEADER_LEN]), 0, &mut in_out)
+        .map_err(|_| ScalpelError::EncryptionError {
+            r: "wrong key or modified image".to_string(),
+        })?;
+
+    let mut bytes = data.to_vec();
+    bytes[start as usize..end as usize].copy_from_slice(plaintext);
+    Ok(bytes)
+}
+
+/// encrypt `start..end` of the file at `path` with the raw key at `key` and write it to `output`
+pub fn encrypt_file(cipher: Cipher, key: &Path, path: &Path, output: &Path, start: u64, end: u64) -> Result<()> {
+    let key = read_key(key, cipher)?;
+    let bytes = encrypt(cipher, &key, read_file(path)?.to_vec(), start, end)?;
+    write_file(output, &bytes)
+}
+
+/// decrypt the file at `path` with the raw key at `key` and write it to `output`,
+/// signature trailers or a plain signature appended after encryption are verified with `keyring` and removed first
+pub fn decrypt_file(key: &Path, path: &Path, output: &Path, keyring: Option<&Keyring>) -> Result<()> {
+    let content = read_file(path)?;
+    let bytes = match keyring {
+        Some(keyring) => decrypt(&read_file(key)?, keyring.verify(&content, 1, path)?)?,
+        None => decrypt(&read_file(key)?, &content)?,
+    };
+    write_file(output, &bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use concat;
+    use signer::Signer;
+    use testing::TempFile;
+    use trailer;
+
+    #[test]
+    fn known_answer() {
+        // AES-128-GCM test case 2 and AES-256-GCM test case 14 of the GCM specification,
+        // the ciphertext does not depend on the authenticated trailer fields
+        let mut bytes = vec![0u8; 16];
+        Cipher::Aes128Gcm.seal(&[0; 16], [0; 12], &mut bytes, 0, 16).expect("Failed to encrypt");
+        assert_eq!(bytes[..16], [0x03, 0x88, 0xda, 0xce, 0x60, 0xb6, 0xa3, 0x92, 0xf3, 0x28, 0xc2, 0xb9, 0x71, 0xb2, 0xfe, 0x78]);
+        assert_eq!(bytes.len(), 16 + TRAILER_LEN);
+        let mut bytes = vec![0u8; 16];
+        Cipher::Aes256Gcm.seal(&[0; 32], [0; 12], &mut bytes, 0, 16).expect("Failed to encrypt");
+        assert_eq!(bytes[..16], [0xce, 0xa7, 0x40, 0x3d, 0x4d, 0x60, 0x6b, 0x6e, 0x07, 0x4e, 0xc5, 0xd3, 0xba, 0xf3, 0x9d, 0x18]);
+
+        // RFC 7539 section 2.8.2, the ciphertext starts at block counter 1 as well
+        let key: Vec<u8> = (0x80..0xA0).collect();
+        let nonce = [0x07, 0, 0, 0, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47];
+        let mut bytes = b"Ladies and Gentlemen of the class of '99".to_vec();
+        Cipher::ChaCha20Poly1305.seal(&key, nonce, &mut bytes, 0, 16).expect("Failed to encrypt");
+        assert_eq!(bytes[..16], [0xd3, 0x1a, 0x8d, 0x34, 0x64, 0x8e, 0x60, 0xdb, 0x7b, 0x86, 0xaf, 0xbc, 0x53, 0xef, 0x7e, 0xc2]);
+        assert_eq!(bytes[16..40], b"emen of the class of '99"[..]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let image = read_file(Path::new("tmp/test_bytes")).expect("Failed to read file").to_vec();
+        for cipher in &[Cipher::Aes128Gcm, Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let key = vec![0x42; cipher.key_len()];
+            // the header stays in clear
+            let encrypted = encrypt(*cipher, &key, image.clone(), 0x100, 2048).expect("Failed to encrypt");
+            assert_eq!(encrypted.len(), 2048 + TRAILER_LEN);
+            assert_eq!(encrypted[..0x100], image[..0x100]);
+            assert_ne!(encrypted[0x100..2048], image[0x100..2048]);
+            assert_eq!(decrypt(&key, &encrypted).expect("Failed to decrypt"), image);
+
+            // the nonce is random
+            assert_ne!(encrypt(*cipher, &key, image.clone(), 0x100, 2048).unwrap(), encrypted);
+
+            assert!(decrypt(&[0x43; 32][..cipher.key_len()], &encrypted).is_err());
+            assert!(decrypt(&key[1..], &encrypted).is_err());
+            let mut modified = encrypted.clone();
+            modified[0x200] ^= 1;
+            assert!(decrypt(&key, &modified).is_err());
+            // the range is authenticated
+            let mut modified = encrypted.clone();
+            modified[2048 + 6] = 0x80;
+            assert!(decrypt(&key, &modified).is_err());
+            // the clear header is not
+            let mut modified = encrypted.clone();
+            modified[0x10] ^= 1;
+            assert_eq!(decrypt(&key, &modified).expect("Failed to decrypt")[0x11..], image[0x11..]);
+        }
+        assert!(encrypt(Cipher::Aes256Gcm, &[0; 16], image.clone(), 0, 2048).is_err());
+        assert!(encrypt(Cipher::Aes256Gcm, &[0; 32], image.clone(), 0, 4096).is_err());
+        assert!(decrypt(&[0; 32], &image).is_err());
+    }
+
+    #[test]
+    fn signed_after_encryption() {
+        let image = TempFile::copy("tmp/test_bytes", "tmp/test_encrypt.bin");
+        let path = image.path();
+        let decrypted = TempFile::new("tmp/test_encrypt-decrypted.bin");
+        let output = decrypted.path();
+        let key_file = TempFile::new("tmp/test_encrypt.key");
+        let key_path = key_file.path();
+        let key = vec![0x42; 32];
+        let encrypted = encrypt(Cipher::Aes256Gcm, &key, read_file(path).unwrap().to_vec(), 0, 2048).expect("Failed to encrypt");
+        write_file(path, &encrypted).expect("Failed to write");
+
+        let signer = Signer::from_pkcs8_file(Path::new("tmp/p256_keypair.pk8")).expect("Failed to read keys");
+        let keyring = Keyring::from(signer.verifier().expect("Failed to get verifier"));
+        let trailers = trailer::sign_file(&[signer], path).expect("Signing failed");
+        concat::append_signature(path, path, &trailers).expect("Failed to append trailer");
+        write_file(key_path, &key).expect("Failed to write key");
+        // signature trailers are not dropped without verification
+        assert!(decrypt_file(key_path, path, output, None).is_err());
+        decrypt_file(key_path, path, output, Some(&keyring)).expect("Failed to decrypt");
+        assert_eq!(read_file(output).unwrap(), read_file(Path::new("tmp/test_bytes")).unwrap());
+        let other = Keyring::from(Signer::random().verifier().expect("Failed to get verifier"));
+        assert!(decrypt_file(key_path, path, output, Some(&other)).is_err());
+
+        // plain signatures have to be verified and removed by the public key
+        let signer = Signer::from_pkcs8_file(Path::new("tmp/ed25519_keypair.pk8")).expect("Failed to read keys");
+        write_file(path, &encrypted).expect("Failed to write");
+        let signature = signer.calculate_signature_of_file(path).expect("Signing failed");
+        concat::append_signature(path, path, &signature).expect("Failed to append signature");
+        assert!(decrypt_file(key_path, path, output, None).is_err());
+        let keyring = Keyring::from(signer.verifier().expect("Failed to get verifier"));
+        decrypt_file(key_path, path, output, Some(&keyring)).expect("Failed to decrypt");
+        assert_eq!(read_file(output).unwrap(), read_file(Path::new("tmp/test_bytes")).unwrap());
+        assert!(decrypt_file(key_path, path, output, Some(&other)).is_err());
+    }
+}
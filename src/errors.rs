@@ -1,3 +1,5 @@
+#![allow(non_local_definitions)]
+
 pub use failure::Error;
 pub use failure::Fail;
 
@@ -5,6 +7,7 @@ use std;
 pub type Result<X> = std::result::Result<X, Error>;
 
 #[derive(Debug, Fail)]
+#[allow(clippy::enum_variant_names)]
 pub enum ScalpelError {
     #[fail(display = "Failed to open.")]
     OpeningError,
@@ -23,6 +26,9 @@ pub enum ScalpelError {
 
     #[fail(display = "Failed to parse Keys from .pk8")]
     ParsePk8Error,
+
+    #[fail(display = "Failed to parse Keys from .pem")]
+    ParsePemError,
     
     #[fail(display = "There is no key in this Signer yet")]
     KeyInitError,
@@ -41,4 +47,49 @@ pub enum ScalpelError {
 
     #[fail(display = "Failed replace a section")]
     ReplaceError,
+
+    #[fail(display = "Invalid record in line {}: {}", line, r)]
+    RecordError {line: usize, r: String},
+
+    #[fail(display = "Checksum mismatch in line {}: expected {:#04X}, calculated {:#04X}", line, expected, actual)]
+    RecordChecksumError {line: usize, expected: u8, actual: u8},
+
+    #[fail(display = "Invalid ELF file: {}", r)]
+    ElfError {r: String},
+
+    #[fail(display = "Malformed PEM armor: {}", r)]
+    PemError {r: String},
+
+    #[fail(display = "Invalid key: {}", r)]
+    KeyError {r: String},
+
+    #[fail(display = "Wrong key type: {}", r)]
+    KeyTypeError {r: String},
+
+    #[fail(display = "Signature does not match")]
+    SignatureError,
+
+    #[fail(display = "Verification failed for {} of {} files", failed, total)]
+    VerificationError {failed: usize, total: usize},
+
+    #[fail(display = "{} {:#X} of {} is not aligned to {:#X}", what, value, binary, sector)]
+    AlignmentError {binary: String, what: String, value: u64, sector: u64},
+
+    #[fail(display = "Malformed signature trailer: {}", r)]
+    TrailerError {r: String},
+
+    #[fail(display = "No public key with fingerprint {} in the keyring", fingerprint)]
+    UnknownKeyError {fingerprint: String},
+
+    #[fail(display = "Only {} of {} required keys signed validly, out of {} signatures", valid, threshold, total)]
+    ThresholdError {valid: usize, threshold: usize, total: usize},
+
+    #[fail(display = "Checksum mismatch, expected {} as stored at {:#X}, actual {}", expected, offset, actual)]
+    ChecksumMismatch {expected: String, actual: String, offset: u64},
+
+    #[fail(display = "Encryption failed: {}", r)]
+    EncryptionError {r: String},
+
+    #[fail(display = "Malformed encryption trailer: {}", r)]
+    EncryptionTrailerError {r: String},
 }
\ No newline at end of file
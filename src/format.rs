@@ -0,0 +1,237 @@
+use bytes::BytesMut;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use align::Alignment;
+use errors::*;
+use ihex;
+use srec::{self, AddressWidth};
+use stitch::FillPattern;
+
+/// File formats binaries can be read from and written to
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Bin,
+    Hex,
+    Srec,
+}
+
+impl Format {
+    /// guess the format by the file extension, defaults to raw binary
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+        {
+            Some(ref ext) if ext == "hex" || ext == "ihex" || ext == "ihx" => Format::Hex,
+            Some(ref ext)
+                if ext == "srec" || ext == "s19" || ext == "s28" || ext == "s37" || ext == "mot" =>
+            {
+                Format::Srec
+            }
+            _ => Format::Bin,
+        }
+    }
+}
+
+/// Execution start address as stored in Intel HEX and S-record files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartAddress {
+    /// CS:IP of 8086 style processors
+    Segment { cs: u16, ip: u16 },
+    /// EIP of 32 bit processors, or the address of an S-record termination record
+    Linear(u32),
+}
+
+/// Data chunks at their absolute addresses, as read from a record based file
+#[derive(Debug, Default)]
+pub struct Chunks {
+    pub data: Vec<(u64, Vec<u8>)>,
+    pub start_address: Option<StartAddress>,
+}
+
+impl Chunks {
+    /// add data at `address`, merging it with the previous chunk if contiguous
+    pub fn push(&mut self, address: u64, bytes: &[u8]) {
+        if let Some(&mut (start, ref mut last)) = self.data.last_mut() {
+            if start + last.len() as u64 == address {
+                last.extend_from_slice(bytes);
+                return;
+            }
+        }
+        self.data.push((address, bytes.to_vec()));
+    }
+
+    /// lay out all chunks in one contiguous image, gaps are filled with `fill_pattern`
+    pub fn flatten(mut self, fill_pattern: &FillPattern) -> Result<Image> {
+        self.data.sort_by_key(|&(address, _)| address);
+
+        let address = self.data.first().map(|&(address, _)| address).unwrap_or(0);
+        let mut bytes = BytesMut::new();
+        for (chunk_address, chunk) in self.data {
+            let offset = (chunk_address - address) as usize;
+            if bytes.len() > offset {
+                return Err(ScalpelError::OverlapError
+                    .context(format!("Data at address {:#X} is defined twice", chunk_address))
+                    .into());
+            }
+            fill_pattern.pad(&mut bytes, offset)?;
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(Image {
+            address,
+            bytes,
+            start_address: self.start_address,
+        })
+    }
+}
+
+/// decode the hex digit pairs of a record in line `line`
+pub fn decode_hex(line: usize, digits: &str) -> Result<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return Err(ScalpelError::RecordError {
+            line,
+            r: "odd number of hex digits".to_string(),
+        }
+        .into());
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| {
+                ScalpelError::RecordError {
+                    line,
+                    r: format!("invalid hex digits \"{}\"", &digits[i..i + 2]),
+                }
+                .into()
+            })
+        })
+        .collect()
+}
+
+/// A contiguous binary and the address its first byte is loaded to
+#[derive(Debug, Default, Clone)]
+pub struct Image {
+    pub address: u64,
+    pub bytes: BytesMut,
+    pub start_address: Option<StartAddress>,
+}
+
+/// read a file in the given format, or guessed by extension if `None`
+pub fn read_image(path: &Path, format: Option<Format>, fill_pattern: &FillPattern) -> Result<Image> {
+    match format.unwrap_or_else(|| Format::from_path(path)) {
+        Format::Bin => Ok(Image {
+            address: 0,
+            bytes: ::stitch::read_file(path)?,
+            start_address: None,
+        }),
+        Format::Hex => ihex::parse(&read_text(path)?)
+            .map_err(|e| ScalpelError::ContentError.context(format!("{:?}: {}", path, e)))?
+            .flatten(fill_pattern),
+        Format::Srec => srec::parse(&read_text(path)?)
+            .map_err(|e| ScalpelError::ContentError.context(format!("{:?}: {}", path, e)))?
+            .flatten(fill_pattern),
+    }
+}
+
+fn read_text(path: &Path) -> Result<String> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path)))?;
+
+    let mut text = String::new();
+    file.read_to_string(&mut text)
+        .map_err(|err| ScalpelError::ReadingError.context(format!("{}: {:?}", err, path)))?;
+    Ok(text)
+}
+
+/// Destination file of a command and how to encode it
+#[derive(Debug)]
+pub struct Output {
+    pub path: PathBuf,
+    pub format: Format,
+    /// maximum number of data bytes per record
+    pub record_length: usize,
+    /// address width of S-records, smallest fitting if `None`
+    pub address_width: Option<AddressWidth>,
+    /// sector size the output length has to be aligned to
+    pub alignment: Option<Alignment>,
+}
+
+impl Output {
+    /// output to `path`, format is guessed by extension if `None`
+    pub fn new(path: PathBuf, format: Option<Format>, record_length: Option<usize>) -> Self {
+        let format = format.unwrap_or_else(|| Format::from_path(&path));
+        Self {
+            path,
+            format,
+            record_length: record_length.unwrap_or(16),
+            address_width: None,
+            alignment: None,
+        }
+    }
+
+    pub fn write(&self, image: &Image) -> Result<()> {
+        // pad a copy if the output length is not aligned
+        let mut padded = None;
+        if let Some(ref alignment) = self.alignment {
+            let len = alignment.length(&self.path, "length", image.bytes.len() as u64)? as usize;
+            if len > image.bytes.len() {
+                let mut copy = image.clone();
+                alignment.pad.unwrap_or_default().pad(&mut copy.bytes, len)?;
+                padded = Some(copy);
+            }
+        }
+        let image = padded.as_ref().unwrap_or(image);
+
+        let content = match self.format {
+            Format::Bin => image.bytes.clone(),
+            Format::Hex => BytesMut::from(ihex::write(image, self.record_length)?.into_bytes()),
+            Format::Srec => BytesMut::from(
+                srec::write(image, self.record_length, self.address_width)?.into_bytes(),
+            ),
+        };
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path)
+            .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, self.path)))?;
+
+        file.write_all(&content)
+            .map_err(|err| ScalpelError::WritingError.context(err))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flatten_fills_gaps() {
+        let mut chunks = Chunks::default();
+        chunks.push(0x104, &[3, 4]);
+        chunks.push(0x100, &[1, 2]);
+        chunks.push(0x106, &[5]);
+        let image = chunks.flatten(&FillPattern::One).expect("Failed to flatten");
+
+        assert_eq!(image.address, 0x100);
+        assert_eq!(&image.bytes[..], &[1, 2, 0xFF, 0xFF, 3, 4, 5]);
+    }
+
+    #[test]
+    fn flatten_rejects_overlap() {
+        let mut chunks = Chunks::default();
+        chunks.push(0x100, &[1, 2, 3]);
+        chunks.push(0x102, &[4]);
+        chunks.push(0x0FF, &[0]);
+        assert!(chunks.flatten(&FillPattern::Zero).is_err());
+    }
+}
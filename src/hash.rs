@@ -0,0 +1,255 @@
+use ring::digest;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use embed::Region;
+use errors::*;
+use stitch::read_file;
+
+/// Digests to stream large files through, i.e. before signing
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+/// How digests are printed
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum DigestFormat {
+    /// `<digest>  <file>` lines as `sha256sum` prints them
+    #[default]
+    Hex,
+    Base64,
+    /// array listing file, algorithm, range and hex digest of each file
+    Json,
+}
+
+impl HashAlgorithm {
+    fn algorithm(self) -> &'static digest::Algorithm {
+        match self {
+            HashAlgorithm::Sha256 => &digest::SHA256,
+            HashAlgorithm::Sha512 => &digest::SHA512,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    /// number of bytes of a digest
+    pub fn len(self) -> usize {
+        self.algorithm().output_len
+    }
+
+    /// digest of `start..end` of the file at `path`, leaving out the slot at `slot` the digest is embedded in
+    pub fn digest_section(self, path: &Path, start: u64, end: u64, slot: Option<u64>) -> Result<FileDigest> {
+        let bytes = read_file(path)?;
+        self.digest_bytes(&bytes, path, start, end, slot)
+    }
+
+    /// compare the digest of `start..end` of the file at `path` with the one stored at `slot`,
+    /// the slot is left out of the range
+    pub fn verify_section(self, path: &Path, start: u64, end: u64, slot: u64) -> Result<FileDigest> {
+        let bytes = read_file(path)?;
+        let digest = self.digest_bytes(&bytes, path, start, end, Some(slot))?;
+        let stored = &bytes[slot as usize..slot as usize + self.len()];
+        if stored != &digest.digest[..] {
+            return Err(ScalpelError::ChecksumMismatch {
+                expected: stored.iter().map(|byte| format!("{:02x}", byte)).collect(),
+                actual: digest.hex(),
+                offset: slot,
+            }
+            .into());
+        }
+        Ok(digest)
+    }
+
+    /// digest of the first `len` bytes of `path`, or all of it, reading `fragment_size` bytes at once
+    pub fn digest_file(self, path: &Path, len: Option<u64>, fragment_size: usize) -> Result<Vec<u8>> {
+        let mut context = digest::Context::new(self.algorithm());
+        stream_file(path, len, fragment_size, |fragment| context.update(fragment))?;
+        Ok(context.finish().as_ref().to_vec())
+    }
+
+    fn digest_bytes(self, bytes: &[u8], path: &Path, start: u64, end: u64, slot: Option<u64>) -> Result<FileDigest> {
+        let region = Region::new(start, end, slot, self.len(), bytes.len() as u64)
+            .map_err(|err| err.context(format!("{:?}", path)))?;
+        Ok(FileDigest {
+            path: path.to_path_buf(),
+            algorithm: self,
+            start,
+            end,
+            digest: digest::digest(self.algorithm(), &region.message(bytes)).as_ref().to_vec(),
+        })
+    }
+}
+
+/// pass the first `len` bytes of `path`, or all of it, to `update` in fragments of `fragment_size` bytes
+pub fn stream_file<F>(path: &Path, len: Option<u64>, fragment_size: usize, mut update: F) -> Result<()>
+where
+    F: FnMut(&[u8]),
+{
+    if fragment_size == 0 {
+        return Err(ScalpelError::ArgumentError
+            .context("Fragment size must not be 0")
+            .into());
+    }
+
+    let file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path)))?;
+    let mut f_in = file.take(len.unwrap_or(u64::MAX));
+
+    let mut fragment = vec![0; fragment_size];
+    let mut total = 0u64;
+    loop {
+        let read = f_in
+            .read(&mut fragment)
+            .map_err(|err| ScalpelError::ReadingError.context(err))?;
+        if read == 0 {
+            break;
+        }
+        update(&fragment[..read]);
+        total += read as u64;
+    }
+
+    if let Some(len) = len {
+        if total != len {
+            return Err(ScalpelError::ContentError
+                .context(format!("{:?} has only {} of {} bytes", path, total, len))
+                .into());
+        }
+    }
+    Ok(())
+}
+
+/// Digest of a section of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileDigest {
+    pub path: PathBuf,
+    pub algorithm: HashAlgorithm,
+    pub start: u64,
+    pub end: u64,
+    pub digest: Vec<u8>,
+}
+
+impl FileDigest {
+    pub fn hex(&self) -> String {
+        self.digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"file\": {}, \"algorithm\": \"{}\", \"start\": {}, \"end\": {}, \"digest\": \"{}\"}}",
+            json_string(&self.path.to_string_lossy()),
+            self.algorithm.name(),
+            self.start,
+            self.end,
+            self.hex()
+        )
+    }
+}
+
+/// quote and escape `value` as JSON string
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `digests` as lines of `<digest>  <file>` or as JSON array, i.e. for an update manifest
+pub fn format_digests(digests: &[FileDigest], format: DigestFormat) -> String {
+    let lines = digests.iter().map(|digest| match format {
+        DigestFormat::Hex => format!("{}  {}", digest.hex(), digest.path.display()),
+        DigestFormat::Base64 => format!("{}  {}", base64::encode(&digest.digest), digest.path.display()),
+        DigestFormat::Json => format!("  {}", digest.to_json()),
+    });
+    match format {
+        DigestFormat::Json => format!("[\n{}\n]", lines.collect::<Vec<_>>().join(",\n")),
+        _ => lines.collect::<Vec<_>>().join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::TempFile;
+
+    #[test]
+    fn streamed_digest() {
+        let path = Path::new("tmp/signme.bin");
+        let mut content = Vec::new();
+        OpenOptions::new()
+            .read(true)
+            .open(path)
+            .and_then(|mut file| file.read_to_end(&mut content))
+            .expect("Failed to read file");
+
+        for hash in &[HashAlgorithm::Sha256, HashAlgorithm::Sha512] {
+            let expected = digest::digest(hash.algorithm(), &content);
+            // fragments smaller than, not dividing and larger than the file
+            for fragment_size in &[7, 64, 1 << 20] {
+                let streamed = hash.digest_file(path, None, *fragment_size).expect("Failed to hash file");
+                assert_eq!(streamed, expected.as_ref());
+            }
+            let partial = hash.digest_file(path, Some(10), 3).expect("Failed to hash file");
+            assert_eq!(partial, digest::digest(hash.algorithm(), &content[..10]).as_ref());
+        }
+
+        assert!(HashAlgorithm::Sha256.digest_file(path, Some(content.len() as u64 + 1), 64).is_err());
+        assert!(HashAlgorithm::Sha256.digest_file(path, None, 0).is_err());
+    }
+
+    #[test]
+    fn section_digest() {
+        let path = Path::new("tmp/test_bytes");
+        let content = read_file(path).expect("Failed to read file");
+
+        let whole = HashAlgorithm::Sha512.digest_section(path, 0, 2048, None).expect("Failed to hash file");
+        assert_eq!(whole.digest, HashAlgorithm::Sha512.digest_file(path, None, 64).unwrap());
+        // the slot is left out
+        let section = HashAlgorithm::Sha256.digest_section(path, 16, 1024, Some(32)).expect("Failed to hash file");
+        let expected = digest::digest(&digest::SHA256, &[&content[16..32], &content[64..1024]].concat());
+        assert_eq!(section.digest, expected.as_ref());
+        assert_eq!(section.hex().len(), 64);
+        assert!(HashAlgorithm::Sha256.digest_section(path, 0, 4096, None).is_err());
+        assert!(HashAlgorithm::Sha256.digest_section(path, 0, 16, Some(2040)).is_err());
+
+        let hex = format_digests(&[whole.clone(), section.clone()], DigestFormat::Hex);
+        assert_eq!(hex, format!("{}  tmp/test_bytes\n{}  tmp/test_bytes", whole.hex(), section.hex()));
+        let json = format_digests(::std::slice::from_ref(&section), DigestFormat::Json);
+        assert_eq!(
+            json,
+            format!(
+                "[\n  {{\"file\": \"tmp/test_bytes\", \"algorithm\": \"sha256\", \"start\": 16, \"end\": 1024, \"digest\": \"{}\"}}\n]",
+                section.hex()
+            )
+        );
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+
+    #[test]
+    fn verify_stored_digest() {
+        let image = TempFile::copy("tmp/test_bytes", "tmp/test_digest_verify.bin");
+        let path = image.path();
+        let embedded = HashAlgorithm::Sha256.digest_section(path, 0, 2048, Some(0x40)).expect("Failed to hash file");
+        ::embed::write_slot(read_file(path).unwrap(), 0x40, &embedded.digest, path).expect("Failed to embed digest");
+        assert_eq!(HashAlgorithm::Sha256.verify_section(path, 0, 2048, 0x40).expect("Verification failed"), embedded);
+        assert!(HashAlgorithm::Sha256.verify_section(path, 0, 2047, 0x40).is_err());
+        assert!(HashAlgorithm::Sha512.verify_section(path, 0, 2048, 0x40).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+use errors::*;
+use format::{decode_hex, Chunks, Image, StartAddress};
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// two's complement checksum over all bytes of a record
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |acc, byte| acc.wrapping_add(*byte))
+        .wrapping_neg()
+}
+
+/// parse Intel HEX text into data chunks at their absolute addresses
+pub fn parse(text: &str) -> Result<Chunks> {
+    let mut chunks = Chunks::default();
+    // upper part of the address, set by extended segment or linear address records
+    let mut base: u64 = 0;
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_nr = idx + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with(':') {
+            return Err(ScalpelError::RecordError {
+                line: line_nr,
+                r: "record does not start with ':'".to_string(),
+            }
+            .into());
+        }
+
+        let record = decode_hex(line_nr, &line[1..])?;
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(ScalpelError::RecordError {
+                line: line_nr,
+                r: "record length does not match byte count".to_string(),
+            }
+            .into());
+        }
+
+        let (content, expected) = record.split_at(record.len() - 1);
+        let actual = checksum(content);
+        if actual != expected[0] {
+            return Err(ScalpelError::RecordChecksumError {
+                line: line_nr,
+                expected: expected[0],
+                actual,
+            }
+            .into());
+        }
+
+        let address = u64::from(u16::from(content[1]) << 8 | u16::from(content[2]));
+        let record_type = content[3];
+        let data = &content[4..];
+
+        let expect_len = |len: usize| -> Result<()> {
+            if data.len() != len {
+                return Err(ScalpelError::RecordError {
+                    line: line_nr,
+                    r: format!("record type {:02X} requires {} data bytes", record_type, len),
+                }
+                .into());
+            }
+            Ok(())
+        };
+
+        match record_type {
+            DATA => chunks.push(base + address, data),
+            END_OF_FILE => return Ok(chunks),
+            EXTENDED_SEGMENT_ADDRESS => {
+                expect_len(2)?;
+                base = u64::from(u16::from(data[0]) << 8 | u16::from(data[1])) << 4;
+            }
+            START_SEGMENT_ADDRESS => {
+                expect_len(4)?;
+                chunks.start_address = Some(StartAddress::Segment {
+                    cs: u16::from(data[0]) << 8 | u16::from(data[1]),
+                    ip: u16::from(data[2]) << 8 | u16::from(data[3]),
+                });
+            }
+            EXTENDED_LINEAR_ADDRESS => {
+                expect_len(2)?;
+                base = u64::from(u16::from(data[0]) << 8 | u16::from(data[1])) << 16;
+            }
+            START_LINEAR_ADDRESS => {
+                expect_len(4)?;
+                chunks.start_address = Some(StartAddress::Linear(
+                    u32::from(data[0]) << 24
+                        | u32::from(data[1]) << 16
+                        | u32::from(data[2]) << 8
+                        | u32::from(data[3]),
+                ));
+            }
+            unknown => {
+                return Err(ScalpelError::RecordError {
+                    line: line_nr,
+                    r: format!("unknown record type {:02X}", unknown),
+                }
+                .into())
+            }
+        }
+    }
+
+    Err(ScalpelError::RecordError {
+        line: text.lines().count(),
+        r: "missing end of file record".to_string(),
+    }
+    .into())
+}
+
+/// format a single record including the leading colon and checksum
+fn record(address: u16, record_type: u8, data: &[u8]) -> String {
+    let mut bytes = Vec::with_capacity(data.len() + 5);
+    bytes.push(data.len() as u8);
+    bytes.push((address >> 8) as u8);
+    bytes.push(address as u8);
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+    let sum = checksum(&bytes);
+    bytes.push(sum);
+
+    bytes.iter().fold(String::from(":"), |mut acc, byte| {
+        acc.push_str(&format!("{:02X}", byte));
+        acc
+    }) + "\n"
+}
+
+/// serialize an image to Intel HEX with at most `record_length` data bytes per record
+pub fn write(image: &Image, record_length: usize) -> Result<String> {
+    if record_length == 0 || record_length > 255 {
+        return Err(ScalpelError::ArgumentError
+            .context(format!(
+                "Record length {} is not within 1..255",
+                record_length
+            ))
+            .into());
+    }
+    if image.address + image.bytes.len() as u64 > 0x1_0000_0000 {
+        return Err(ScalpelError::ArgumentError
+            .context("Intel HEX can only address the first 4Gi")
+            .into());
+    }
+
+    let mut out = String::new();
+    let mut upper: Option<u64> = None;
+    let mut offset = 0usize;
+    while offset < image.bytes.len() {
+        let address = image.address + offset as u64;
+        if upper != Some(address >> 16) {
+            let ela = (address >> 16) as u16;
+            out.push_str(&record(0, EXTENDED_LINEAR_ADDRESS, &[(ela >> 8) as u8, ela as u8]));
+            upper = Some(address >> 16);
+        }
+        // records must not cross a 64Ki boundary
+        let to_boundary = (0x1_0000 - (address & 0xFFFF)) as usize;
+        let len = record_length
+            .min(to_boundary)
+            .min(image.bytes.len() - offset);
+        out.push_str(&record(
+            address as u16,
+            DATA,
+            &image.bytes[offset..offset + len],
+        ));
+        offset += len;
+    }
+
+    match image.start_address {
+        Some(StartAddress::Segment { cs, ip }) => out.push_str(&record(
+            0,
+            START_SEGMENT_ADDRESS,
+            &[(cs >> 8) as u8, cs as u8, (ip >> 8) as u8, ip as u8],
+        )),
+        Some(StartAddress::Linear(eip)) => out.push_str(&record(
+            0,
+            START_LINEAR_ADDRESS,
+            &[
+                (eip >> 24) as u8,
+                (eip >> 16) as u8,
+                (eip >> 8) as u8,
+                eip as u8,
+            ],
+        )),
+        None => {}
+    }
+    out.push_str(&record(0, END_OF_FILE, &[]));
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::BytesMut;
+    use stitch::FillPattern;
+
+    #[test]
+    fn parse_with_extended_addresses() {
+        let text = ":020000040800F2\n\
+                    :0400000001020304F2\n\
+                    :020000021000EC\n\
+                    :020010000506E3\n\
+                    :0400000508000101ED\n\
+                    :00000001FF\n";
+        let chunks = parse(text).expect("Failed to parse hex");
+        assert_eq!(chunks.data[0], (0x0800_0000, vec![1, 2, 3, 4]));
+        assert_eq!(chunks.data[1], (0x1_0010, vec![5, 6]));
+        assert_eq!(chunks.start_address, Some(StartAddress::Linear(0x0800_0101)));
+    }
+
+    #[test]
+    fn reject_bad_checksum() {
+        let text = ":0400000001020304F3\n:00000001FF\n";
+        assert!(parse(text).is_err());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let image = Image {
+            address: 0x0800_FFF8,
+            bytes: BytesMut::from((0u8..32).collect::<Vec<u8>>()),
+            start_address: Some(StartAddress::Segment { cs: 0x1234, ip: 0x5678 }),
+        };
+        let text = write(&image, 16).expect("Failed to write hex");
+        // the 64Ki boundary forces a second extended linear address record
+        assert_eq!(text.matches(":02000004").count(), 2);
+
+        let parsed = parse(&text)
+            .expect("Failed to parse written hex")
+            .flatten(&FillPattern::Zero)
+            .expect("Failed to flatten");
+        assert_eq!(parsed.address, image.address);
+        assert_eq!(parsed.bytes, image.bytes);
+        assert_eq!(parsed.start_address, image.start_address);
+    }
+}
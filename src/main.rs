@@ -1,6 +1,3 @@
-#[macro_use]
-extern crate lazy_static;
-extern crate regex;
 
 #[macro_use]
 extern crate log;
@@ -14,11 +11,17 @@ extern crate ring;
 extern crate serde;
 #[macro_use]
 extern crate common_failures;
-#[macro_use]
 extern crate failure;
 extern crate rand;
+extern crate base64;
+extern crate aes;
+extern crate ed25519_dalek;
+extern crate p256;
+extern crate rsa;
+extern crate sha2;
 
 use docopt::Docopt;
+use std::fs;
 use std::path::{PathBuf, Path};
 
 mod signer;
@@ -26,51 +29,145 @@ use signer::*;
 
 mod concat;
 mod cut;
+mod elf;
+mod embed;
+mod encrypt;
 mod errors;
+mod align;
+mod block_cipher;
 mod byte_offset;
+mod checksum;
+mod format;
+mod ihex;
+mod srec;
+mod hash;
 mod stitch;
+mod trailer;
 mod replace;
+#[cfg(test)]
+mod testing;
 use errors::*;
 use byte_offset::*;
+use format::{Format, Output};
 
-const USAGE: &'static str = "
+const USAGE: &str = "
 scalpel
 
 Usage:
-  scalpel cut [--fragment=<fragment>] [--start=<start>] --end=<end> --output=<output> <file>
-  scalpel cut [--fragment=<fragment>] [--start=<start>] --size=<size> --output=<output> <file>
-  scalpel sign <keyfile> [--output=<output>] [--format=<format>] <file>
-  scalpel sign <keyfile> <files>...
-  scalpel stitch (--binary=<binary> --offset=<offset>)... --output=<output> [--fill-pattern=<fill_pattern>]
-  scalpel replace [--start=<start>] --end=<end> --replace=<replace> --output=<output> <input> [--fill-pattern=<fill_pattern>]
-  scalpel replace [--start=<start>] --size=<size> --replace=<replace> --output=<output> <input> [--fill-pattern=<fill_pattern>]
+  scalpel cut [--fragment=<fragment>] [--symbols=<symbols>] [--start=<start>] --end=<end> --output=<output> [--input-format=<input_format>] [--output-format=<output_format>] [--record-length=<record_length>] [--address-width=<address_width>] [--fill-pattern=<fill_pattern>] [--align=<sector> [--pad-to-align]] <file>
+  scalpel cut [--fragment=<fragment>] [--symbols=<symbols>] [--start=<start>] --size=<size> --output=<output> [--input-format=<input_format>] [--output-format=<output_format>] [--record-length=<record_length>] [--address-width=<address_width>] [--fill-pattern=<fill_pattern>] [--align=<sector> [--pad-to-align]] <file>
+  scalpel cut (--section=<section> | --segment=<segment> | --flatten) --output=<output> [--fill-pattern=<fill_pattern>] [--output-format=<output_format>] [--record-length=<record_length>] [--address-width=<address_width>] [--align=<sector> [--pad-to-align]] <file>
+  scalpel sign <keyfile> [--output=<output> | --in-place] [--format=<format>] [--rsa-padding=<rsa_padding>] [--detached [--signature-format=<signature_format>] | --trailer] [--cosign=<cosign>]... [--prehash=<hash> [--fragment=<fragment>]] <file>
+  scalpel sign <keyfile> [--output-dir=<output_dir> | --in-place] [--format=<format>] [--rsa-padding=<rsa_padding>] [--detached [--signature-format=<signature_format>] | --trailer] [--cosign=<cosign>]... [--prehash=<hash> [--fragment=<fragment>]] <files>...
+  scalpel sign <keyfile> [--output=<output>] [--format=<format>] [--rsa-padding=<rsa_padding>] [--symbols=<symbols>] --range=<range> --signature-offset=<signature_offset> <file>
+  scalpel verify (--pubkey=<pubkey> | --keyring=<keyring>) [--rsa-padding=<rsa_padding>] [--threshold=<threshold>] [--prehash=<hash> [--fragment=<fragment>]] <files>...
+  scalpel verify --pubkey=<pubkey> [--rsa-padding=<rsa_padding>] [--prehash=<hash> [--fragment=<fragment>]] --signature=<signature> <file>
+  scalpel verify --pubkey=<pubkey> [--rsa-padding=<rsa_padding>] [--symbols=<symbols>] --range=<range> --signature-offset=<signature_offset> <files>...
+  scalpel keygen [--algorithm=<algorithm>] --out=<out> --pubout=<pubout>
+  scalpel stitch (--binary=<binary> --offset=<offset>)... --output=<output> [--fill-pattern=<fill_pattern>] [--input-format=<input_format>] [--output-format=<output_format>] [--record-length=<record_length>] [--address-width=<address_width>] [--align=<sector> [--pad-to-align]]
+  scalpel replace [--symbols=<symbols>] [--start=<start>] --end=<end> --replace=<replace> --output=<output> <input> [--fill-pattern=<fill_pattern>] [--input-format=<input_format>] [--output-format=<output_format>] [--record-length=<record_length>] [--address-width=<address_width>] [--align=<sector> [--pad-to-align]]
+  scalpel replace [--symbols=<symbols>] [--start=<start>] --size=<size> --replace=<replace> --output=<output> <input> [--fill-pattern=<fill_pattern>] [--input-format=<input_format>] [--output-format=<output_format>] [--record-length=<record_length>] [--address-width=<address_width>] [--align=<sector> [--pad-to-align]]
+  scalpel checksum [--crc=<crc> | --width=<width> --poly=<poly> [--init=<init>] [--refin] [--refout] [--xorout=<xorout>]] [--symbols=<symbols>] [--start=<start>] [--end=<end> | --size=<size>] [--embed=<embed> | --append] [--endian=<endian>] [--output=<output>] <file>
+  scalpel checksum --verify=<stored> [--crc=<crc> | --width=<width> --poly=<poly> [--init=<init>] [--refin] [--refout] [--xorout=<xorout>] | --hash=<hash>] [--symbols=<symbols>] [--start=<start>] [--end=<end> | --size=<size>] [--endian=<endian>] <files>...
+  scalpel digest [--hash=<hash>] [--symbols=<symbols>] [--start=<start>] [--end=<end> | --size=<size>] [--digest-format=<digest_format>] <files>...
+  scalpel digest [--hash=<hash>] [--symbols=<symbols>] [--start=<start>] [--end=<end> | --size=<size>] [--digest-format=<digest_format>] --embed=<embed> [--output=<output>] <file>
+  scalpel encrypt --key=<key> [--cipher=<cipher>] [--symbols=<symbols>] [--start=<start>] [--end=<end> | --size=<size>] [--output=<output>] <file>
+  scalpel decrypt --key=<key> [--pubkey=<pubkey> [--rsa-padding=<rsa_padding>] | --keyring=<keyring>] [--output=<output>] <file>
+  scalpel encrypt --key=<key> --mode=<mode> --iv-offset=<iv_offset> [--padding=<padding>] [--symbols=<symbols>] [--start=<start>] [--end=<end> | --size=<size>] [--sign=<sign> [--format=<format>] [--rsa-padding=<rsa_padding>]] [--output=<output>] <file>
+  scalpel decrypt --key=<key> --mode=<mode> --iv-offset=<iv_offset> [--padding=<padding>] [--symbols=<symbols>] [--start=<start>] [--end=<end> | --size=<size>] [--pubkey=<pubkey> [--rsa-padding=<rsa_padding>]] [--output=<output>] <file>
   scalpel (-h | --help)
   scalpel (-v |--version)
 
 Commands:
   cut       extract bytes from a binary file
-  sign      sign binary with a keypair such as ED25519 or RSA
+  sign      sign binary with an ED25519, RSA or ECDSA P-256 keypair
+  verify    verify the signature appended to binaries or a detached signature with a public key only
+  keygen    generate a keypair, the public key is written raw to <pubout>, as DER to <pubout>.der and as PEM to <pubout>.pem
   stitch    stitchs binaries together, each file starts at <offset> with random padding
   replace     replace a section with <replace> specfied by start and end/size
+  checksum  calculate a CRC of a section, print it or embed it at an offset or append it to <file> or <output>,
+            or compare the CRC or digest of each of <files> with the one stored at <stored>
+  digest    calculate SHA-256 or SHA-512 digests of sections, print them or embed one at an offset of <file> or <output>
+  encrypt   encrypt a section with AES-GCM or ChaCha20-Poly1305 and append nonce and tag as trailer,
+            or with AES-CTR or AES-CBC and a random IV stored at <iv_offset> as ROM bootloaders expect it
+  decrypt   decrypt an encrypted binary, signature trailers or plain signatures appended afterwards
+            are verified with <pubkey> or <keyring> and removed, signed binaries are refused without
 
 Options:
   -h --help                     Show this screen.
   -v --version                  Show version.
-  --start=<start>               Start byte offset of the section to cut out. If omitted, set to 0.
-  --end=<end>                   The end byte offset which will not be included.
+  --start=<start>               Start byte offset of the section to cut out, negative from the end of the input. If omitted, set to 0.
+  --end=<end>                   The end byte offset which will not be included, negative from the end of the input.
   --size=<size>                 Alternate way to sepcify the <end> combined with start.
   --fragment=<fragment>         Define the size of the fragment/chunk to read/write at once. [Default: 8192]
   --format=<format>             Specify the key format, eihter pkcs8, pem, bytes or new
   --fill-pattern=<fill_patern>  Specify padding style for stitching (random|one|zero)
   --replace=<replace>               file which replaces the original part
+  --algorithm=<algorithm>           Algorithm of the generated keypair (ed25519|p256) [default: ed25519]
+  --out=<out>                       PKCS#8 file for the generated private key, only readable by the owner
+  --pubout=<pubout>                 File for the generated public key
+  --pubkey=<pubkey>                 ED25519, RSA or ECDSA P-256 public key to verify signatures with, PEM, DER or raw
+  --rsa-padding=<rsa_padding>       Padding of RSA signatures (pkcs1|pss), ignored for other keys [default: pkcs1]
+  --detached                        Write the signature to <file>.sig instead of appending it to a copy
+  --in-place                        Append the signature to <file> itself instead of <name>-signed.<ext>
+  --output-dir=<output_dir>         Directory to write the signed files or detached signatures to
+  --signature-format=<signature_format>  Encoding of the detached signature (raw|base64|pem) [default: raw]
+  --signature=<signature>           Detached signature of <file>, raw, base64 or PEM
+  --trailer                         Append a trailer with magic, version, algorithm, key fingerprint, signed length and signature
+  --keyring=<keyring>               Directory of public keys, the key is selected by the fingerprint in the signature trailer
+  --cosign=<cosign>                 Further keyfile in <format> to add a signature trailer with, signed files keep their trailers
+  --threshold=<threshold>           Number of distinct keys of the keyring which have to have signed each file [default: 1]
+  --range=<range>                   Sign only the bytes <start>..<end>, without the signature slot, and embed the signature in place
+  --signature-offset=<signature_offset>  Offset of the slot within <file> the signature is written to
+  --prehash=<hash>                  Stream the file through a digest in <fragment> sized chunks and sign the digest (sha256|sha512),
+                                    RSA and ECDSA sign it as the message hash, Ed25519 uses Ed25519ph with sha512
+  --symbols=<symbols>               ELF file to resolve offsets such as sym:<name>[+<offset>] to its file offsets
+  --section=<section>               Cut out the file bytes of an ELF section such as .text
+  --segment=<segment>               Cut out the loadable ELF segment with this program header index
+  --flatten                         Cut out all loadable ELF segments as one image, gaps are padded with <fill_pattern>
+  --input-format=<input_format>     Format of the input files (bin|hex|srec), guessed by file extension if omitted
+  --output-format=<output_format>   Format of the output file (bin|hex|srec), guessed by file extension if omitted
+  --record-length=<record_length>   Number of data bytes per record for hex and srec output [Default: 16]
+  --address-width=<address_width>   Address width of srec output in bits (16|24|32), smallest fitting if omitted
+  --align=<sector>                  Reject start, offset, size and output length if not a multiple of the sector size
+  --pad-to-align                    Pad the output to a multiple of <sector> with <fill_pattern> instead of rejecting it
+  --crc=<crc>                       Named CRC such as CRC-32/ISO-HDLC, CRC-16/CCITT-FALSE or CRC-32C [default: CRC-32/ISO-HDLC]
+  --width=<width>                   Bits of a custom CRC (8|16|32|64)
+  --poly=<poly>                     Polynomial of a custom CRC, without the top bit
+  --init=<init>                     Initial value of a custom CRC
+  --refin                           Reflect the input bytes of a custom CRC
+  --refout                          Reflect the result of a custom CRC
+  --xorout=<xorout>                 Value a custom CRC is XORed with at last
+  --embed=<embed>                   Offset to write the checksum or digest to, which leaves its own slot out
+  --append                          Append the checksum
+  --endian=<endian>                 Byte order of the written checksum (little|big) [default: little]
+  --hash=<hash>                     Digest to calculate (sha256|sha512), sha256 if omitted
+  --verify=<stored>                 Offset of the stored checksum or digest to compare with, its slot is left out of the section
+  --key=<key>                       Raw key file, 16 bytes for aes128gcm and 32 bytes for aes256gcm and chacha20poly1305
+  --cipher=<cipher>                 Cipher to encrypt with (aes128gcm|aes256gcm|chacha20poly1305) [default: aes256gcm]
+  --mode=<mode>                     Unauthenticated AES mode (ctr|cbc), AES-128, -192 or -256 by the length of <key>
+  --iv-offset=<iv_offset>           Offset of the 16 byte IV slot, outside of the encrypted section
+  --padding=<padding>               Pad the section to the AES block size (none|pkcs7|zero), PKCS#7 is removed by decrypt [default: none]
+  --sign=<sign>                     Keyfile in <format> to append a signature of the encrypted binary with
+  --digest-format=<digest_format>   Print digests as <digest>  <file> lines (hex|base64) or as JSON manifest (json) [default: hex]
+
+Offsets and sizes can be expressions of numbers, `end` (the input length, for stitch the length of that binary),
+`sym:<name>` and `align(<value>, <alignment>)` combined by + - * / and parentheses, i.e. --start=end-64
+A leading - counts from the end of the input, i.e. --start=-64
 ";
 
 #[derive(Debug, Deserialize)]
 struct Args {
     cmd_cut: bool,
     cmd_sign: bool,
+    cmd_verify: bool,
+    cmd_keygen: bool,
     cmd_stitch: bool,
     cmd_replace: bool,
+    cmd_checksum: bool,
+    cmd_digest: bool,
+    cmd_encrypt: bool,
+    cmd_decrypt: bool,
     flag_start: Option<ByteOffset>,
     flag_end: Option<ByteOffset>,
     flag_size: Option<ByteOffset>,
@@ -85,12 +182,223 @@ struct Args {
     flag_fill_pattern: Option<stitch::FillPattern>,
     flag_format: Option<String>,
     flag_replace: PathBuf,
+    flag_pubkey: Option<PathBuf>,
+    flag_algorithm: Option<KeyAlgorithm>,
+    flag_rsa_padding: Option<RsaPadding>,
+    flag_detached: bool,
+    flag_in_place: bool,
+    flag_output_dir: Option<PathBuf>,
+    flag_signature_format: Option<concat::SignatureFormat>,
+    flag_signature: Option<PathBuf>,
+    flag_trailer: bool,
+    flag_cosign: Vec<String>,
+    flag_threshold: Option<usize>,
+    flag_keyring: Option<PathBuf>,
+    flag_prehash: Option<hash::HashAlgorithm>,
+    flag_range: Option<ByteRange>,
+    flag_signature_offset: Option<ByteOffset>,
+    flag_out: Option<PathBuf>,
+    flag_pubout: Option<PathBuf>,
+    flag_symbols: Option<PathBuf>,
+    flag_section: Option<String>,
+    flag_segment: Option<usize>,
+    flag_flatten: bool,
+    flag_input_format: Option<Format>,
+    flag_output_format: Option<Format>,
+    flag_record_length: Option<usize>,
+    flag_address_width: Option<u32>,
+    flag_align: Option<ByteOffset>,
+    flag_pad_to_align: bool,
+    flag_crc: String,
+    flag_width: Option<u32>,
+    flag_poly: Option<String>,
+    flag_init: Option<String>,
+    flag_refin: bool,
+    flag_refout: bool,
+    flag_xorout: Option<String>,
+    flag_embed: Option<ByteOffset>,
+    flag_append: bool,
+    flag_endian: Option<checksum::Endian>,
+    flag_hash: Option<hash::HashAlgorithm>,
+    flag_digest_format: Option<hash::DigestFormat>,
+    flag_verify: Option<ByteOffset>,
+    flag_key: Option<PathBuf>,
+    flag_cipher: Option<encrypt::Cipher>,
+    flag_mode: Option<block_cipher::Mode>,
+    flag_iv_offset: Option<ByteOffset>,
+    flag_padding: Option<block_cipher::Padding>,
+    flag_sign: Option<PathBuf>,
     flag_version: bool,
     flag_help: bool,
 }
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const NAME: &'static str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const NAME: &str = env!("CARGO_PKG_NAME");
+
+/// determine start and end of a section from start and either end or size,
+/// `input_len` is what `end` refers to within the expressions, the end has to fit into 64 bits
+fn start_and_end(
+    start: Option<&ByteOffset>,
+    end: Option<&ByteOffset>,
+    size: Option<&ByteOffset>,
+    input_len: u64,
+    symbols: Option<&Path>,
+) -> Result<(u64, u64)> {
+    let symbols = match symbols {
+        Some(path) => Some(elf::Elf::from_file(path)?),
+        None => None,
+    };
+    let symbols = symbols.as_ref();
+
+    let start = match start {
+        Some(start) => start.resolve(Some(input_len), symbols)?,
+        None => 0,
+    };
+    let end = if let Some(end) = end {
+        if size.is_some() {
+            return Err(ScalpelError::ArgumentError
+                .context("Either end or size has to be specified, not both")
+                .into());
+        }
+        let end = end.resolve(Some(input_len), symbols)?;
+        if start >= end {
+            return Err(ScalpelError::ArgumentError
+                .context(format!(
+                    "end addr {1} should be larger than start addr {0}",
+                    start, end
+                ))
+                .into());
+        }
+        end
+    } else if let Some(size) = size {
+        let size = size.resolve(Some(input_len), symbols)?;
+        start.checked_add(size).ok_or_else(|| {
+            ScalpelError::ArgumentError.context(format!("Section of {} bytes at {} does not fit into 64 bits", size, start))
+        })?
+    } else {
+        return Err(ScalpelError::ArgumentError
+            .context("Either end addr or size has to be specified")
+            .into());
+    };
+    Ok((start, end))
+}
+
+/// start and end of the section of an input of `len` bytes, all of it if neither end nor size is given
+fn section_from_args(args: &Args, len: u64) -> Result<(u64, u64)> {
+    let whole = ByteOffset::parse("end")?;
+    let end = match (&args.flag_end, &args.flag_size) {
+        (None, None) => Some(&whole),
+        (end, _) => end.as_ref(),
+    };
+    start_and_end(args.flag_start.as_ref(), end, args.flag_size.as_ref(), len, args.flag_symbols.as_deref())
+}
+
+/// resolve `offset` within an input of `len` bytes, with the symbols if given
+fn offset_from_args(args: &Args, offset: &ByteOffset, len: u64) -> Result<u64> {
+    let symbols = match args.flag_symbols {
+        Some(ref path) => Some(elf::Elf::from_file(path)?),
+        None => None,
+    };
+    offset.resolve(Some(len), symbols.as_ref())
+}
+
+/// the named CRC or the custom one given by width and its parameters
+fn crc_from_args(args: &Args) -> Result<checksum::Crc> {
+    if let Some(width) = args.flag_width {
+        let parameter = |value: &Option<String>| value.as_ref().map_or(Ok(0), |value| checksum::parse_parameter(value));
+        checksum::Crc::new(
+            width,
+            parameter(&args.flag_poly)?,
+            parameter(&args.flag_init)?,
+            args.flag_refin,
+            args.flag_refout,
+            parameter(&args.flag_xorout)?,
+        )
+    } else {
+        checksum::Crc::preset(&args.flag_crc)
+    }
+}
+
+/// output file and its encoding as given by the format options
+fn output_from_args(args: &Args) -> Result<Output> {
+    let path = PathBuf::from(args.flag_output.as_ref().unwrap());
+    let mut output = Output::new(path, args.flag_output_format, args.flag_record_length);
+    if let Some(bits) = args.flag_address_width {
+        output.address_width = Some(srec::AddressWidth::from_bits(bits)?);
+    }
+    if let Some(ref sector) = args.flag_align {
+        let pad = if args.flag_pad_to_align {
+            Some(args.flag_fill_pattern.unwrap_or_default())
+        } else {
+            None
+        };
+        output.alignment = Some(align::Alignment::new(sector.resolve(None, None)?, pad)?);
+    }
+    Ok(output)
+}
+
+/// digest to stream files through before signing, with the number of bytes to read at once
+fn prehash_from_args(args: &Args) -> Result<Option<(hash::HashAlgorithm, usize)>> {
+    match args.flag_prehash {
+        Some(hash) => {
+            let fragment_size = match args.flag_fragment {
+                Some(ref fragment) => fragment.resolve(None, None)?,
+                None => 0,
+            };
+            Ok(Some((hash, fragment_size as usize)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// signature to append to `path`, either bare, of its digest or as signature trailer
+///
+/// Signature trailers are appended for each of `signers`, otherwise there is a single signer.
+fn signature_of_file(
+    signers: &[Signer],
+    path: &Path,
+    with_trailer: bool,
+    prehash: Option<(hash::HashAlgorithm, usize)>,
+) -> Result<Vec<u8>> {
+    match prehash {
+        Some(_) if with_trailer => Err(ScalpelError::ArgumentError
+            .context("Signature trailers can not be combined with --prehash")
+            .into()),
+        Some((hash, fragment_size)) => signers[0].calculate_prehashed_signature_of_file(path, hash, fragment_size),
+        None if with_trailer => trailer::sign_file(signers, path),
+        None => signers[0].calculate_signature_of_file(path),
+    }
+}
+
+/// where to write the signed `input` or its signature, `derived` unless --output, --output-dir or --in-place is given
+fn sign_output(args: &Args, input: &Path, derived: PathBuf) -> PathBuf {
+    if args.flag_in_place {
+        input.to_path_buf()
+    } else if let Some(ref output) = args.flag_output {
+        PathBuf::from(output)
+    } else if let Some(ref dir) = args.flag_output_dir {
+        match derived.file_name() {
+            Some(name) => dir.join(name),
+            None => derived,
+        }
+    } else {
+        derived
+    }
+}
+
+/// load a signing key in `key_format`
+fn signer_from_file(key_path: &Path, key_format: &str) -> Result<Signer> {
+    Ok(match key_format {
+        "pkcs8" => Signer::from_pkcs8_file(key_path)?,
+        "pem" => Signer::from_pem_file(key_path)?,
+        "generate" => Signer::random(),
+        fmt => {
+            return Err(ScalpelError::ArgumentError
+                .context(format!("File Format not recognized {}", fmt))
+                .into())
+        }
+    })
+}
 
 fn run() -> Result<()> {
     env_logger::init();
@@ -109,131 +417,438 @@ fn run() -> Result<()> {
     } else if args.cmd_sign {
         // command sign
 
+        let prehash = prehash_from_args(&args)?;
+
         // get keys from the specified input file
-        let key_format = args.flag_format.unwrap_or("pkcs8".to_string());
-        let signer = match key_format.as_str() {
-            "pkcs8" => {
-                let key_path = &args.arg_keyfile.as_ref();
-                Signer::from_pkcs8_file(&key_path)?
-            }
-            "pem" => {
-                unimplemented!("can you suggest a parser?");
-            }
-            "generate" => Signer::random(),
-            fmt => {
-                return Err(ScalpelError::ArgumentError
-                    .context(format!("File Format not recognized {}", fmt))
-                    .into())
-            }
+        let key_format = args.flag_format.clone().unwrap_or_else(|| "pkcs8".to_string());
+        let rsa_padding = args.flag_rsa_padding.unwrap_or_default();
+        let signer = signer_from_file(args.arg_keyfile.as_ref(), &key_format)?.with_rsa_padding(rsa_padding);
+
+        // every further key appends its own signature trailer
+        let mut signers = vec![signer];
+        for cosign in args.flag_cosign.iter() {
+            signers.push(signer_from_file(cosign.as_ref(), &key_format)?.with_rsa_padding(rsa_padding));
+        }
+        let with_trailer = args.flag_trailer || signers.len() > 1;
+        if signers.len() > 1 && (args.flag_detached || args.flag_range.is_some()) {
+            return Err(ScalpelError::ArgumentError
+                .context("Multiple signatures are appended as trailers, not detached or embedded")
+                .into());
+        }
+        let signer = &signers[0];
+
+        let symbols = match args.flag_symbols {
+            Some(ref path) => Some(elf::Elf::from_file(path)?),
+            None => None,
         };
-        
-        if args.arg_files.len() > 0 {
-            for item in args.arg_files.iter() {
-                // get signature
-                let signature = signer.calculate_signature_of_file(item)?;
-                // create signed file
-                concat::append_signature(item.as_ref(), &signature)?;
+
+        if let Some(ref range) = args.flag_range {
+            // embed the signature into the slot, of the file itself unless --output is given
+            let path_victim = Path::new(&args.arg_file);
+            let output = sign_output(&args, path_victim, path_victim.to_path_buf());
+            let slot = args.flag_signature_offset.as_ref().unwrap();
+            let region = embed::sign_region(signer, path_victim, &output, range, slot, symbols.as_ref())?;
+            embed::verify_region(&signer.verifier()?, &output, range, slot, symbols.as_ref())?;
+            info!("signing success: {:?}, {}..{} signed into {:#X}", output, region.start, region.end, region.slot);
+            return Ok(());
+        }
+
+        let verifier = signer.verifier()?;
+        let keyring = trailer::Keyring::from(&signers[..]);
+        let verify_appended = |path: &Path| match prehash {
+            Some((hash, fragment_size)) => verifier.verify_prehashed_file(path, hash, fragment_size),
+            None => keyring.verify_file(path, signers.len()).map(|_| ()),
+        };
+
+        let files = if args.arg_files.is_empty() {
+            vec![args.arg_file.clone()]
+        } else {
+            args.arg_files.clone()
+        };
+        for item in files.iter() {
+            let path_victim = Path::new(item);
+            if args.flag_detached {
+                // get signature, the file itself stays untouched
+                let signature = match prehash {
+                    Some((hash, fragment_size)) => {
+                        signer.calculate_prehashed_signature_of_file(path_victim, hash, fragment_size)?
+                    }
+                    None => signer.calculate_signature_of_file(path_victim)?,
+                };
+                let signature_filename = sign_output(&args, path_victim, concat::derive_signature_filename(path_victim));
+                concat::write_detached_signature(
+                    &signature_filename,
+                    &signature,
+                    args.flag_signature_format.unwrap_or_default(),
+                )?;
 
                 // verify
-                let signed_filename = concat::derive_output_filename(Path::new(item))?;
-                signer.verify_file(&signed_filename)?;
-                info!("signing success: \"{}\"", &signed_filename.as_str());
+                let signature = concat::read_detached_signature(&signature_filename, verifier.signature_len())?;
+                match prehash {
+                    Some((hash, fragment_size)) => {
+                        verifier.verify_prehashed_detached(path_victim, &signature, hash, fragment_size)?
+                    }
+                    None => verifier.verify_detached(path_victim, &signature)?,
+                }
+                info!("signing success: {:?}", signature_filename);
+            } else {
+                // get signature
+                let signature = signature_of_file(&signers, path_victim, with_trailer, prehash)?;
+
+                // create signed file, or append to the file itself
+                let signed_filename = sign_output(
+                    &args,
+                    path_victim,
+                    PathBuf::from(concat::derive_output_filename(path_victim)?),
+                );
+                concat::append_signature(path_victim, &signed_filename, &signature)?;
+
+                // test the verification
+                verify_appended(&signed_filename)?;
+                info!("signing success: {:?}", signed_filename);
             }
-        } else {
-            let path_victim = &args.arg_file.as_ref();
-            // get signature
-            let signature = signer.calculate_signature_of_file(path_victim)?;
+        }
+        Ok(())
+    } else if args.cmd_verify {
+        // command verify, only the public key is required
+        let verifier = match args.flag_pubkey {
+            Some(ref pubkey) => Some(
+                Verifier::from_file(pubkey)?.with_rsa_padding(args.flag_rsa_padding.unwrap_or_default()),
+            ),
+            None => None,
+        };
+        let prehash = prehash_from_args(&args)?;
+        if prehash.is_some() && args.flag_keyring.is_some() {
+            return Err(ScalpelError::ArgumentError
+                .context("Signature trailers can not be combined with --prehash, use --pubkey")
+                .into());
+        }
 
-            // create signed file
-            concat::append_signature(path_victim, &signature)?;
+        let symbols = match args.flag_symbols {
+            Some(ref path) => Some(elf::Elf::from_file(path)?),
+            None => None,
+        };
 
-            // test the verification
-            let signed_filename = args
-                .flag_output
-                .unwrap_or(concat::derive_output_filename(path_victim)?);
+        if let Some(ref signature_path) = args.flag_signature {
+            let verifier = verifier.unwrap();
+            let signature = concat::read_detached_signature(signature_path, verifier.signature_len())?;
+            let verified = match prehash {
+                Some((hash, fragment_size)) => {
+                    verifier.verify_prehashed_detached(Path::new(&args.arg_file), &signature, hash, fragment_size)
+                }
+                None => verifier.verify_detached(&args.arg_file, &signature),
+            };
+            return match verified {
+                Ok(()) => {
+                    println!("{}: OK", args.arg_file);
+                    Ok(())
+                }
+                Err(e) => {
+                    println!("{}: FAILED ({})", args.arg_file, e);
+                    Err(ScalpelError::VerificationError { failed: 1, total: 1 }.into())
+                }
+            };
+        }
 
-            signer.verify_file(&signed_filename)?;
+        // signature trailers select their key from the keyring, a single public key is a keyring as well
+        let keyring = match args.flag_keyring {
+            Some(ref dir) => trailer::Keyring::from_dir(dir)?,
+            None => trailer::Keyring::from(verifier.clone().unwrap()),
+        };
+
+        let threshold = args.flag_threshold.unwrap_or(1);
+        let failed = args.arg_files.iter().filter(|item| {
+            let verified = match (prehash, &verifier) {
+                // signatures embedded into a slot of the file
+                (_, Some(verifier)) if args.flag_range.is_some() => embed::verify_region(
+                    verifier,
+                    Path::new(item),
+                    args.flag_range.as_ref().unwrap(),
+                    args.flag_signature_offset.as_ref().unwrap(),
+                    symbols.as_ref(),
+                ),
+                (Some((hash, fragment_size)), Some(verifier)) => {
+                    verifier.verify_prehashed_file(Path::new(item), hash, fragment_size)
+                }
+                _ => keyring.verify_file(item, threshold).map(|_| ()),
+            };
+            match verified {
+                Ok(()) => {
+                    println!("{}: OK", item);
+                    false
+                }
+                Err(e) => {
+                    println!("{}: FAILED ({})", item, e);
+                    true
+                }
+            }
+        }).count();
 
-            info!("signing success: \"{}\"", signed_filename.as_str());
+        if failed > 0 {
+            return Err(ScalpelError::VerificationError {
+                failed,
+                total: args.arg_files.len(),
+            }
+            .into());
         }
         Ok(())
+    } else if args.cmd_keygen {
+        // command keygen
+        let out = args.flag_out.as_ref().unwrap();
+        let pubout = args.flag_pubout.as_ref().unwrap();
+        keygen(args.flag_algorithm.unwrap_or_default(), out, pubout)?;
+        info!("keygen success: {:?}, {:?}", out, pubout);
+        Ok(())
     } else if args.cmd_cut {
         // command cut
 
         // do input handling
-        let start = args.flag_start.unwrap_or(Default::default()).as_u64(); // if none, set to 0
-        let size: u64 = if let Some(end) = args.flag_end {
-            if let Some(_) = args.flag_size {
-                return Err(ScalpelError::ArgumentError
-                    .context("Either end or size has to be specified, not both")
-                    .into());
-            }
-            let end = end.as_u64();
-            if start >= end {
-                return Err(ScalpelError::ArgumentError
-                    .context(format!(
-                        "end addr {1} should be larger than start addr {0}",
-                        start, end
-                    ))
-                    .into());
-            }
-            end - start
-        } else if let Some(size) = args.flag_size {
-            let size = size.as_u64();
-            size
+        let output = output_from_args(&args)?;
+        let fill_pattern = args.flag_fill_pattern.unwrap_or_default();
+
+        // extract parts of an ELF file
+        if args.flag_section.is_some() || args.flag_segment.is_some() || args.flag_flatten {
+            let elf = elf::Elf::from_file(Path::new(&args.arg_file))?;
+            let image = if let Some(ref section) = args.flag_section {
+                elf.section_image(section)?
+            } else if let Some(segment) = args.flag_segment {
+                elf.segment_image(segment)?
+            } else {
+                elf.flatten(&fill_pattern)?
+            };
+            output.write(&image)?;
+            info!("Cutting success");
+            return Ok(());
+        }
+
+        let fragment_size = args.flag_fragment.unwrap_or_default().resolve(None, None)?; // CHUNK 8192 from cut
+        
+        let input = PathBuf::from(&args.arg_file);
+        let input_format = args.flag_input_format.unwrap_or_else(|| Format::from_path(&input));
+
+        // raw binaries are copied without reading all of the input
+        let image = if input_format == Format::Bin && output.format == Format::Bin {
+            None
         } else {
-            return Err(ScalpelError::ArgumentError
-                .context("Either end addr or size has to be specified")
-                .into());
+            Some(format::read_image(&input, Some(input_format), &fill_pattern)?)
         };
-        let fragment_size = args.flag_fragment.unwrap_or(Default::default()).as_u64(); // CHUNK 8192 from cut
-        
-        cut::cut_out_bytes(
-            args.arg_file,
-            args.flag_output.unwrap(),
-            start,
-            size,
-            fragment_size as usize,
-        ).and_then(|_| {
-            info!("Cutting success");
-            Ok(())
-        })
+        let input_len = match image {
+            Some(ref image) => image.bytes.len() as u64,
+            None => fs::metadata(&input)
+                .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, input)))?
+                .len(),
+        };
+        let (start, end) = start_and_end(args.flag_start.as_ref(), args.flag_end.as_ref(), args.flag_size.as_ref(), input_len, args.flag_symbols.as_deref())?;
+        let size = end - start;
+
+        let mut padded = false;
+        if let Some(ref alignment) = output.alignment {
+            alignment.check(&input, "start", start)?;
+            let section = format!("length of section {:#X}..{:#X}", start, end);
+            padded = alignment.length(&input, &section, size)? != size;
+        }
+
+        match image {
+            None if !padded => cut::cut_out_bytes(
+                args.arg_file,
+                output.path.to_string_lossy().into_owned(),
+                start,
+                size,
+                fragment_size as usize,
+            )?,
+            image => {
+                let image = match image {
+                    Some(image) => image,
+                    None => format::read_image(&input, Some(input_format), &fill_pattern)?,
+                };
+                output.write(&cut::cut_image(image, start, size)?)?;
+            }
+        }
+        info!("Cutting success");
+        Ok(())
     } else if args.cmd_stitch {
         // command stitch binaries together
         
-        stitch::stitch_files(args.flag_binary, args.flag_offset, args.flag_output.unwrap(), args.flag_fill_pattern.unwrap_or_default() )?;
+        let output = output_from_args(&args)?;
+        stitch::stitch_files(args.flag_binary, args.flag_offset, &output, args.flag_fill_pattern.unwrap_or_default(), args.flag_input_format)?;
 
         Ok(())
     } else if args.cmd_replace {
         // do input handling
-        let start = args.flag_start.unwrap_or(Default::default()).as_u64(); // if none, set to 0
-        let size: u64 = if let Some(end) = args.flag_end {
-            if let Some(_) = args.flag_size {
-                return Err(ScalpelError::ArgumentError
-                    .context("Either end or size has to be specified, not both")
-                    .into());
-            }
-            let end = end.as_u64();
-            if start >= end {
-                return Err(ScalpelError::ArgumentError
-                    .context(format!(
-                        "end addr {1} should be larger than start addr {0}",
-                        start, end
-                    ))
-                    .into());
+        let output = output_from_args(&args)?;
+        let fill_pattern = args.flag_fill_pattern.unwrap_or_default();
+        let image = format::read_image(&args.arg_input, args.flag_input_format, &fill_pattern)?;
+        let (start, end) = start_and_end(args.flag_start.as_ref(), args.flag_end.as_ref(), args.flag_size.as_ref(), image.bytes.len() as u64, args.flag_symbols.as_deref())?;
+        let size = end - start;
+        if let Some(ref alignment) = output.alignment {
+            alignment.check(&args.arg_input, "start", start)?;
+            alignment.check(&args.arg_input, "size", size)?;
+        }
+
+        replace::replace_file(&args.flag_replace, image, &output, start, size, fill_pattern, args.flag_input_format)?;
+
+        Ok(())
+    } else if args.cmd_checksum {
+        let crc = crc_from_args(&args)?;
+
+        // compare with the stored checksum or digest instead
+        if let Some(ref stored) = args.flag_verify {
+            let failed = args.arg_files.iter().filter(|item| {
+                let path = Path::new(item);
+                let verified = fs::metadata(path)
+                    .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path)).into())
+                    .and_then(|metadata| {
+                        let (start, end) = section_from_args(&args, metadata.len())?;
+                        let slot = offset_from_args(&args, stored, metadata.len())?;
+                        match args.flag_hash {
+                            Some(hash) => hash.verify_section(path, start, end, slot).map(|_| ()),
+                            None => checksum::verify_checksum(&crc, path, start, end, slot, args.flag_endian.unwrap_or_default()).map(|_| ()),
+                        }
+                    });
+                match verified {
+                    Ok(()) => {
+                        println!("{}: OK", item);
+                        false
+                    }
+                    Err(e) => {
+                        println!("{}: FAILED ({})", item, e);
+                        true
+                    }
+                }
+            }).count();
+
+            if failed > 0 {
+                return Err(ScalpelError::VerificationError {
+                    failed,
+                    total: args.arg_files.len(),
+                }
+                .into());
             }
-            end - start
-        } else if let Some(size) = args.flag_size {
-            let size = size.as_u64();
-            size
+            return Ok(());
+        }
+
+        let path = Path::new(&args.arg_file);
+        let len = fs::metadata(path)
+            .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path)))?
+            .len();
+        let (start, end) = section_from_args(&args, len)?;
+        let placement = match args.flag_embed {
+            Some(ref offset) => Some(checksum::Placement::Embed(offset_from_args(&args, offset, len)?)),
+            None if args.flag_append => Some(checksum::Placement::Append),
+            None => None,
+        };
+
+        let value = checksum::checksum_file(&crc, path, start, end, placement)?;
+        println!("{}: {:#0width$X}", args.arg_file, value, width = 2 + 2 * crc.len());
+        if let Some(placement) = placement {
+            let output = args.flag_output.as_ref().map_or(path, Path::new);
+            checksum::write_checksum(&crc, value, args.flag_endian.unwrap_or_default(), path, output, placement)?;
+        }
+
+        Ok(())
+    } else if args.cmd_digest {
+        let hash = args.flag_hash.unwrap_or(hash::HashAlgorithm::Sha256);
+        let files = if args.arg_files.is_empty() {
+            vec![args.arg_file.clone()]
         } else {
-            return Err(ScalpelError::ArgumentError
-                .context("Either end addr or size has to be specified")
-                .into());
+            args.arg_files.clone()
+        };
+
+        let mut digests = Vec::with_capacity(files.len());
+        for file in &files {
+            let path = Path::new(file);
+            let len = fs::metadata(path)
+                .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path)))?
+                .len();
+            let (start, end) = section_from_args(&args, len)?;
+            let slot = match args.flag_embed {
+                Some(ref offset) => Some(offset_from_args(&args, offset, len)?),
+                None => None,
+            };
+
+            let digest = hash.digest_section(path, start, end, slot)?;
+            if let Some(slot) = slot {
+                let output = args.flag_output.as_ref().map_or(path, Path::new);
+                embed::write_slot(stitch::read_file(path)?, slot, &digest.digest, output)?;
+            }
+            digests.push(digest);
+        }
+        println!("{}", hash::format_digests(&digests, args.flag_digest_format.unwrap_or_default()));
+
+        Ok(())
+    } else if args.cmd_encrypt {
+        let path = Path::new(&args.arg_file);
+        let len = fs::metadata(path)
+            .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path)))?
+            .len();
+        let (start, end) = section_from_args(&args, len)?;
+        let output = match args.flag_output {
+            Some(ref output) => PathBuf::from(output),
+            None => PathBuf::from(concat::derive_filename(path, "encrypted")?),
         };
 
-        replace::replace_file(args.flag_replace, args.arg_input, args.flag_output.unwrap(), start, size, args.flag_fill_pattern.unwrap_or_default())?;
+        if let Some(mode) = args.flag_mode {
+            let iv_offset = offset_from_args(&args, args.flag_iv_offset.as_ref().unwrap(), len)?;
+            let signer = match args.flag_sign {
+                Some(ref keyfile) => {
+                    let key_format = args.flag_format.clone().unwrap_or_else(|| "pkcs8".to_string());
+                    Some(signer_from_file(keyfile, &key_format)?.with_rsa_padding(args.flag_rsa_padding.unwrap_or_default()))
+                }
+                None => None,
+            };
+            let layout = block_cipher::Layout {
+                mode,
+                padding: args.flag_padding.unwrap_or_default(),
+                start,
+                end,
+                iv_offset,
+            };
+            block_cipher::encrypt_file(args.flag_key.as_ref().unwrap(), &layout, path, &output, signer.as_ref())?;
+            info!("encrypted {:?} to {:?}", path, output);
+            return Ok(());
+        }
+
+        encrypt::encrypt_file(args.flag_cipher.unwrap_or_default(), args.flag_key.as_ref().unwrap(), path, &output, start, end)?;
+        info!("encrypted {:?} to {:?}", path, output);
+        Ok(())
+    } else if args.cmd_decrypt {
+        let path = Path::new(&args.arg_file);
+        let output = match args.flag_output {
+            Some(ref output) => PathBuf::from(output),
+            None => PathBuf::from(concat::derive_filename(path, "decrypted")?),
+        };
 
+        let verifier = match args.flag_pubkey {
+            Some(ref pubkey) => Some(Verifier::from_file(pubkey)?.with_rsa_padding(args.flag_rsa_padding.unwrap_or_default())),
+            None => None,
+        };
+        if let Some(mode) = args.flag_mode {
+            // offsets refer to the encrypted binary without its signature
+            let len = fs::metadata(path)
+                .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path)))?
+                .len()
+                .saturating_sub(verifier.as_ref().map_or(0, |verifier| verifier.signature_len() as u64));
+            let (start, end) = section_from_args(&args, len)?;
+            let iv_offset = offset_from_args(&args, args.flag_iv_offset.as_ref().unwrap(), len)?;
+            let layout = block_cipher::Layout {
+                mode,
+                padding: args.flag_padding.unwrap_or_default(),
+                start,
+                end,
+                iv_offset,
+            };
+            block_cipher::decrypt_file(args.flag_key.as_ref().unwrap(), &layout, path, &output, verifier.as_ref())?;
+            info!("decrypted {:?} to {:?}", path, output);
+            return Ok(());
+        }
+
+        let keyring = match (args.flag_keyring, verifier) {
+            (Some(ref dir), _) => Some(trailer::Keyring::from_dir(dir)?),
+            (None, Some(verifier)) => Some(trailer::Keyring::from(verifier)),
+            (None, None) => None,
+        };
+        encrypt::decrypt_file(args.flag_key.as_ref().unwrap(), path, &output, keyring.as_ref())?;
+        info!("decrypted {:?} to {:?}", path, output);
         Ok(())
     } else {
         Err(ScalpelError::ArgumentError
@@ -1,25 +1,24 @@
 use bytes::{BytesMut};
-use std::path::{Path, PathBuf};
+use std::path::{Path};
 use stitch::FillPattern;
 use errors::*;
-use rand::{Rng};
-use std::fs::OpenOptions;
-use std::io::Write;
+use format::{self, Format, Image, Output};
 
-pub fn replace_file(replace_path: PathBuf, input: PathBuf, output: String, start: u64, size: u64, fill_pattern: FillPattern) -> Result<()> {
+/// replace `size` bytes of `image` at `start` with the content of `replace_path` and write the result to `output`
+pub fn replace_file(replace_path: &Path, mut image: Image, output: &Output, start: u64, size: u64, fill_pattern: FillPattern, input_format: Option<Format>) -> Result<()> {
 
-    let content = ::stitch::read_file(input.as_ref())?;
-    let replace_bytes = ::stitch::read_file(replace_path.as_ref())?;
+    let replace_bytes = format::read_image(replace_path, input_format, &fill_pattern)?.bytes;
 
-    let replaced = replace(replace_bytes, content, start as usize, size as usize, fill_pattern)?;
+    image.bytes = replace(replace_bytes, image.bytes, start as usize, size as usize, fill_pattern)?;
 
-    write_file(Path::new(&output), replaced)?;
+    output.write(&image)?;
     
     Ok(())
 }
 
 
-fn replace(replace: BytesMut, mut output: BytesMut, start: usize, size: usize, fill_pattern: FillPattern) -> Result<BytesMut> {
+/// replace `size` bytes of `output` at `start` with `replace`, padded with `fill_pattern`
+pub fn replace(replace: BytesMut, mut output: BytesMut, start: usize, size: usize, fill_pattern: FillPattern) -> Result<BytesMut> {
 
     if replace.len() > size {
         return Err(ScalpelError::ReplaceError.context(format!("Size {} of file larger than size {} of replacement section", replace.len(),size)).into());
@@ -33,15 +32,7 @@ fn replace(replace: BytesMut, mut output: BytesMut, start: usize, size: usize, f
     output.extend_from_slice(&replace);
 
     // fill missing bytes
-    match fill_pattern {
-        FillPattern::Zero => output.resize(length+size, 0x0),
-        FillPattern::One => output.resize(length+size, 0xFF),
-        FillPattern::Random => {
-            let mut padding = vec![0; size-replace.len()];
-            ::rand::thread_rng().try_fill(&mut padding[..])?;
-            output.extend_from_slice(&padding);
-        },
-    }
+    fill_pattern.pad(&mut output, length+size)?;
 
     // append the end
     output.extend_from_slice(&after[size..]);
@@ -49,32 +40,22 @@ fn replace(replace: BytesMut, mut output: BytesMut, start: usize, size: usize, f
     Ok(output)
 }
 
-fn write_file(path: &Path, bytes: BytesMut) -> Result<()> {
-    
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(path)
-        .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path )))?;
-
-    file.write(&bytes)?;
-
-    Ok(())
-}
-
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::fs::OpenOptions;
     use std::io::{Read};
+    use std::path::PathBuf;
 
     #[test]
     fn replace_a_bit() {
-        let input =  PathBuf::from("tmp/test_bytes");
+        let input = format::read_image(Path::new("tmp/test_bytes"), None, &FillPattern::One)
+            .expect("Failed to read input");
         let replacing = PathBuf::from("tmp/signme.bin");
+        let output = Output::new(PathBuf::from("tmp/replaced"), None, None);
 
-        replace_file(replacing, input, "tmp/replaced".to_string(), 0, 630, FillPattern::One)
+        replace_file(&replacing, input, &output, 0, 630, FillPattern::One, None)
             .expect("Failed to replace file");
 
         let buf = {
@@ -96,4 +77,4 @@ mod test {
 
 
 
-}
\ No newline at end of file
+}
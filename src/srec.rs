@@ -0,0 +1,331 @@
+use errors::*;
+use format::{decode_hex, Chunks, Image, StartAddress};
+
+/// Width of the address field of data records, which also selects the record types
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressWidth {
+    /// S1 data and S9 termination records
+    Bits16,
+    /// S2 data and S8 termination records
+    Bits24,
+    /// S3 data and S7 termination records
+    Bits32,
+}
+
+impl AddressWidth {
+    pub fn from_bits(bits: u32) -> Result<Self> {
+        match bits {
+            16 => Ok(AddressWidth::Bits16),
+            24 => Ok(AddressWidth::Bits24),
+            32 => Ok(AddressWidth::Bits32),
+            _ => Err(ScalpelError::ArgumentError
+                .context(format!("Address width must be 16, 24 or 32 bits, not {}", bits))
+                .into()),
+        }
+    }
+
+    /// smallest width which can address all bytes below `end`
+    fn fitting(end: u64) -> Self {
+        if end <= 0x1_0000 {
+            AddressWidth::Bits16
+        } else if end <= 0x100_0000 {
+            AddressWidth::Bits24
+        } else {
+            AddressWidth::Bits32
+        }
+    }
+
+    fn len(self) -> usize {
+        match self {
+            AddressWidth::Bits16 => 2,
+            AddressWidth::Bits24 => 3,
+            AddressWidth::Bits32 => 4,
+        }
+    }
+
+    fn limit(self) -> u64 {
+        1u64 << (8 * self.len())
+    }
+
+    fn data_type(self) -> u8 {
+        match self {
+            AddressWidth::Bits16 => b'1',
+            AddressWidth::Bits24 => b'2',
+            AddressWidth::Bits32 => b'3',
+        }
+    }
+
+    fn termination_type(self) -> u8 {
+        match self {
+            AddressWidth::Bits16 => b'9',
+            AddressWidth::Bits24 => b'8',
+            AddressWidth::Bits32 => b'7',
+        }
+    }
+}
+
+/// ones' complement of the sum over count, address and data bytes
+fn checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |acc, byte| acc.wrapping_add(*byte))
+}
+
+/// parse S19/S28/S37 text into data chunks at their load addresses
+pub fn parse(text: &str) -> Result<Chunks> {
+    let mut chunks = Chunks::default();
+    let mut data_records = 0u64;
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_nr = idx + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let line_bytes = line.as_bytes();
+        if line_bytes.len() < 4 || line_bytes[0] != b'S' {
+            return Err(ScalpelError::RecordError {
+                line: line_nr,
+                r: "record does not start with 'S'".to_string(),
+            }
+            .into());
+        }
+        if !line.is_ascii() {
+            return Err(ScalpelError::RecordError {
+                line: line_nr,
+                r: "record contains non-ASCII characters".to_string(),
+            }
+            .into());
+        }
+        let record_type = line_bytes[1];
+
+        let record = decode_hex(line_nr, &line[2..])?;
+        if record.len() != record[0] as usize + 1 {
+            return Err(ScalpelError::RecordError {
+                line: line_nr,
+                r: "record length does not match byte count".to_string(),
+            }
+            .into());
+        }
+
+        let (content, expected) = record.split_at(record.len() - 1);
+        let actual = checksum(content);
+        if actual != expected[0] {
+            return Err(ScalpelError::RecordChecksumError {
+                line: line_nr,
+                expected: expected[0],
+                actual,
+            }
+            .into());
+        }
+
+        let address_len = match record_type {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => {
+                return Err(ScalpelError::RecordError {
+                    line: line_nr,
+                    r: format!("unknown record type S{}", record_type as char),
+                }
+                .into())
+            }
+        };
+        if content.len() < 1 + address_len {
+            return Err(ScalpelError::RecordError {
+                line: line_nr,
+                r: "record too short for its address field".to_string(),
+            }
+            .into());
+        }
+        let address = content[1..=address_len]
+            .iter()
+            .fold(0u64, |acc, byte| acc << 8 | u64::from(*byte));
+        let data = &content[1 + address_len..];
+
+        match record_type {
+            // header, contents are vendor specific
+            b'0' => {}
+            b'1' | b'2' | b'3' => {
+                chunks.push(address, data);
+                data_records += 1;
+            }
+            b'5' | b'6' => {
+                if address != data_records {
+                    return Err(ScalpelError::RecordError {
+                        line: line_nr,
+                        r: format!(
+                            "record count {} does not match {} preceding data records",
+                            address, data_records
+                        ),
+                    }
+                    .into());
+                }
+            }
+            _ => {
+                // the termination record always carries an address, 0 is the default of inputs without one
+                if address != 0 {
+                    chunks.start_address = Some(StartAddress::Linear(address as u32));
+                }
+                return Ok(chunks);
+            }
+        }
+    }
+
+    Err(ScalpelError::RecordError {
+        line: text.lines().count(),
+        r: "missing termination record".to_string(),
+    }
+    .into())
+}
+
+/// format a single record with an address field of `address_len` bytes
+fn record(record_type: u8, address: u64, address_len: usize, data: &[u8]) -> String {
+    let mut bytes = Vec::with_capacity(data.len() + address_len + 2);
+    bytes.push((address_len + data.len() + 1) as u8);
+    for i in (0..address_len).rev() {
+        bytes.push((address >> (8 * i)) as u8);
+    }
+    bytes.extend_from_slice(data);
+    let sum = checksum(&bytes);
+    bytes.push(sum);
+
+    bytes.iter().fold(
+        format!("S{}", record_type as char),
+        |mut acc, byte| {
+            acc.push_str(&format!("{:02X}", byte));
+            acc
+        },
+    ) + "\n"
+}
+
+/// serialize an image to S-records, the address width defaults to the smallest fitting one
+pub fn write(image: &Image, record_length: usize, width: Option<AddressWidth>) -> Result<String> {
+    let end = image.address + image.bytes.len() as u64;
+    let width = width.unwrap_or_else(|| AddressWidth::fitting(end));
+    if end > width.limit() {
+        return Err(ScalpelError::ArgumentError
+            .context(format!(
+                "End address {:#X} does not fit into {} bit S-records",
+                end,
+                8 * width.len()
+            ))
+            .into());
+    }
+    let max_length = 255 - width.len() - 1;
+    if record_length == 0 || record_length > max_length {
+        return Err(ScalpelError::ArgumentError
+            .context(format!(
+                "Record length {} is not within 1..{}",
+                record_length, max_length
+            ))
+            .into());
+    }
+
+    let mut out = record(b'0', 0, 2, b"scalpel");
+    let mut count = 0u64;
+    for (idx, chunk) in image.bytes.chunks(record_length).enumerate() {
+        let address = image.address + (idx * record_length) as u64;
+        out.push_str(&record(width.data_type(), address, width.len(), chunk));
+        count += 1;
+    }
+    if count <= 0xFFFF {
+        out.push_str(&record(b'5', count, 2, &[]));
+    } else if count <= 0xFF_FFFF {
+        out.push_str(&record(b'6', count, 3, &[]));
+    }
+
+    let start = match image.start_address {
+        Some(StartAddress::Linear(eip)) => u64::from(eip),
+        Some(StartAddress::Segment { cs, ip }) => (u64::from(cs) << 4) + u64::from(ip),
+        None => 0,
+    };
+    out.push_str(&record(
+        width.termination_type(),
+        start & (width.limit() - 1),
+        width.len(),
+        &[],
+    ));
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::BytesMut;
+    use stitch::FillPattern;
+
+    #[test]
+    fn parse_s19() {
+        let text = "S00F000068656C6C6F202020202000003C\n\
+                    S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026\n\
+                    S11F001C4BFFFFE5398000007D83637880010014382100107C0803A64E800020E9\n\
+                    S111003848656C6C6F20776F726C642E0A0042\n\
+                    S5030003F9\n\
+                    S9030000FC\n";
+        let image = parse(text)
+            .expect("Failed to parse srec")
+            .flatten(&FillPattern::Zero)
+            .expect("Failed to flatten");
+        assert_eq!(image.address, 0);
+        assert_eq!(image.bytes.len(), 0x38 + 14);
+        assert_eq!(&image.bytes[0x38..0x44], b"Hello world.");
+        assert_eq!(image.start_address, None);
+
+        let image = parse("S111003848656C6C6F20776F726C642E0A0042\nS9031000EC\n")
+            .expect("Failed to parse srec")
+            .flatten(&FillPattern::Zero)
+            .expect("Failed to flatten");
+        assert_eq!(image.start_address, Some(StartAddress::Linear(0x1000)));
+    }
+
+    #[test]
+    fn reject_bad_checksum() {
+        let text = "S111003848656C6C6F20776F726C642E0A0043\nS9030000FC\n";
+        match parse(text) {
+            Err(e) => assert!(format!("{}", e).contains("Checksum mismatch in line 1")),
+            Ok(_) => panic!("Accepted a corrupted record"),
+        }
+    }
+
+    #[test]
+    fn reject_non_ascii() {
+        match parse("S\u{e9}0000\n") {
+            Err(e) => assert!(format!("{}", e).contains("Invalid record in line 1")),
+            Ok(_) => panic!("Accepted a non-ASCII record"),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        for &width in &[AddressWidth::Bits24, AddressWidth::Bits32] {
+            let image = Image {
+                address: 0x8000,
+                bytes: BytesMut::from((0u8..100).collect::<Vec<u8>>()),
+                start_address: Some(StartAddress::Linear(0x8004)),
+            };
+            let text = write(&image, 32, Some(width)).expect("Failed to write srec");
+            assert!(text.lines().nth(1).unwrap().starts_with(match width {
+                AddressWidth::Bits24 => "S2",
+                _ => "S3",
+            }));
+
+            let parsed = parse(&text)
+                .expect("Failed to parse written srec")
+                .flatten(&FillPattern::Zero)
+                .expect("Failed to flatten");
+            assert_eq!(parsed.address, image.address);
+            assert_eq!(parsed.bytes, image.bytes);
+            assert_eq!(parsed.start_address, image.start_address);
+        }
+    }
+
+    #[test]
+    fn too_narrow() {
+        let image = Image {
+            address: 0xFFFF,
+            bytes: BytesMut::from(vec![0u8; 2]),
+            start_address: None,
+        };
+        assert!(write(&image, 16, Some(AddressWidth::Bits16)).is_err());
+    }
+}
@@ -5,34 +5,66 @@ use std::path::{Path, PathBuf};
 use errors::*;
 use rand::{Rng};
 use byte_offset::*;
+use format::{self, Format, Image, Output};
 
-#[derive(Deserialize, Debug)]
-pub enum FillPattern { Random, Zero, One}
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+pub enum FillPattern { Random, #[default] Zero, One}
 
-impl Default for FillPattern {
-    fn default() -> Self {
-        FillPattern::Zero
+impl FillPattern {
+    /// pad `bytes` with the pattern until it is `len` bytes long
+    pub fn pad(&self, bytes: &mut BytesMut, len: usize) -> Result<()> {
+        if bytes.len() >= len {
+            return Ok(());
+        }
+        match self {
+            FillPattern::Zero => bytes.resize(len, 0x0),
+            FillPattern::One => bytes.resize(len, 0xFF),
+            FillPattern::Random => {
+                let mut padding = vec![0; len - bytes.len()];
+                ::rand::thread_rng().try_fill(&mut padding[..])?;
+                bytes.extend_from_slice(&padding);
+            },
+        }
+        Ok(())
     }
 }
 
-pub fn stitch_files(files: Vec<PathBuf>, offsets: Vec<ByteOffset>, output: String, fill_pattern: FillPattern) -> Result<()> {
+/// stitch `files` together, each located at its offset, `end` within an offset is the length of its file
+pub fn stitch_files(files: Vec<PathBuf>, offsets: Vec<ByteOffset>, output: &Output, fill_pattern: FillPattern, input_format: Option<Format>) -> Result<()> {
     
-    let offsets: Vec<usize> = offsets.iter().map(|ele| ele.as_usize()).collect();
-
-    let (files, offsets) = sort_vec_by_offset(files, offsets)?;
-
-    let stitched: Result<BytesMut>
-     = files.iter().zip(offsets.iter()).try_fold(BytesMut::new(), |stitched, (elem, offset)| {
-        let content = read_file(elem.as_ref())
+    let images = files.into_iter().map(|file| {
+        let image = format::read_image(file.as_ref(), input_format, &fill_pattern)
             .map_err(|e| {
-                return ScalpelError::OpeningError.context(e)
+                ScalpelError::OpeningError.context(e)
             })?;
-        
-        Ok(stitch(stitched, content, offset, &fill_pattern).map_err(|e| ScalpelError::OverlapError.context(format!("Failed to stitch {:?}: {}", elem, e)))?)
-        
+        Ok((file, image))
+    }).collect::<Result<Vec<(PathBuf, Image)>>>()?;
+
+    let offsets = offsets.iter().zip(images.iter()).map(|(ele, (file, image))| {
+        let offset = ele.resolve(Some(image.bytes.len() as u64), None)?;
+        if let Some(ref alignment) = output.alignment {
+            alignment.check(file, "offset", offset)?;
+        }
+        Ok(offset as usize)
+    }).collect::<Result<Vec<usize>>>()?;
+
+    let (images, offsets) = sort_vec_by_offset(images, offsets)?;
+
+    let stitched: Result<Image>
+     = images.into_iter().zip(offsets.iter()).try_fold(Image::default(), |mut stitched, ((elem, image), offset)| {
+        // the output is located where the image with the lowest offset expects to be loaded
+        if stitched.bytes.is_empty() {
+            stitched.address = image.address.saturating_sub(*offset as u64);
+        }
+        if stitched.start_address.is_none() {
+            stitched.start_address = image.start_address;
+        }
+
+        stitched.bytes = stitch(stitched.bytes, image.bytes, offset, &fill_pattern).map_err(|e| ScalpelError::OverlapError.context(format!("Failed to stitch {:?}: {}", elem, e)))?;
+        Ok(stitched)
     });
 
-    write_file(Path::new(&output), stitched?)?;
+    output.write(&stitched?)?;
 
     Ok(())
 }
@@ -50,38 +82,30 @@ pub fn read_file(name: &Path) -> Result<BytesMut> {
     Ok(BytesMut::from(buf))
 }
 
+/// create or truncate the file at `name` and write `bytes` to it
+pub fn write_file(name: &Path, bytes: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(name)
+        .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, name)))?;
+    file.write_all(bytes)
+        .map_err(|err| ScalpelError::WritingError.context(err))?;
+    Ok(())
+}
+
 fn stitch(mut bytes: BytesMut, new: BytesMut, offset: &usize, fill_pattern: &FillPattern) -> Result<BytesMut> {
     if bytes.len() > *offset {
-        return Err(ScalpelError::OverlapError.context(format!("Offset {} is smaller than length {} of previous binaries", offset, bytes.len())).into());
+        Err(ScalpelError::OverlapError.context(format!("Offset {} is smaller than length {} of previous binaries", offset, bytes.len())).into())
     } else {
-        match fill_pattern {
-            FillPattern::Zero => bytes.resize(*offset, 0x0),
-            FillPattern::One => bytes.resize(*offset, 0xFF),
-            FillPattern::Random => {
-                let mut padding = vec![0; *offset-bytes.len()];
-                ::rand::thread_rng().try_fill(&mut padding[..])?;
-                bytes.extend_from_slice(&padding);
-            },
-        }
+        fill_pattern.pad(&mut bytes, *offset)?;
         bytes.extend_from_slice(&new);
         debug!("Length: {}", &bytes.len());
         Ok(bytes)
     }
 }
 
-fn write_file(path: &Path, bytes: BytesMut) -> Result<()> {
-    
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(path)
-        .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, path )))?;
-
-    file.write(&bytes)?;
-
-    Ok(())
-}
-
 pub fn sort_vec_by_offset<T>(vec: Vec<T>, offset: Vec<usize>) -> Result<(Vec<T>, Vec<usize>)>
 where T: Clone,
 {
@@ -101,13 +125,16 @@ where T: Clone,
 #[cfg(test)]
 mod test {
     use super::*;
+    use align::Alignment;
+    use testing::TempFile;
 
     #[test]
     fn stitch_it() {
         let files = vec![ PathBuf::from("tmp/test_bytes"), PathBuf::from("tmp/test_bytes")];
 
         let offsets = vec![ByteOffset::new(0,Magnitude::Unit), ByteOffset::new(2,Magnitude::Ki)];
-        super::stitch_files(files, offsets, "stitched_test".to_string(), FillPattern::Zero).expect("Failed to stitch two files");
+        let output = Output::new(PathBuf::from("stitched_test"), None, None);
+        super::stitch_files(files, offsets, &output, FillPattern::Zero, None).expect("Failed to stitch two files");
         let buf = {
             let mut file = OpenOptions::new()
                 .read(true)
@@ -121,5 +148,39 @@ mod test {
         assert_eq!(buf.len(), 4096);
     }
 
+    #[test]
+    fn stitch_hex() {
+        let files = vec![ PathBuf::from("tmp/test_bytes"), PathBuf::from("tmp/test_bytes")];
+
+        let offsets = vec![ByteOffset::new(0,Magnitude::Unit), ByteOffset::new(4,Magnitude::Ki)];
+        let stitched = TempFile::new("tmp/stitched_test.hex");
+        let output = Output::new(stitched.path().to_path_buf(), None, Some(32));
+        super::stitch_files(files, offsets, &output, FillPattern::One, None).expect("Failed to stitch two files");
+
+        let image = format::read_image(stitched.path(), None, &FillPattern::Zero)
+            .expect("Failed to read stitched hex file");
+        assert_eq!(image.bytes.len(), 6144);
+        assert_eq!(image.bytes[2048..4096], [0xFF; 2048][..]);
+    }
 
+    #[test]
+    fn stitch_aligned() {
+        let files = vec![ PathBuf::from("tmp/test_bytes"), PathBuf::from("tmp/test_bytes")];
+
+        let stitched = TempFile::new("tmp/stitched_test_aligned");
+        let mut output = Output::new(stitched.path().to_path_buf(), None, None);
+        output.alignment = Some(Alignment::new(4096, None).expect("Failed to create alignment"));
+        let offsets = vec![ByteOffset::new(0,Magnitude::Unit), ByteOffset::new(2,Magnitude::Ki)];
+        let error = super::stitch_files(files.clone(), offsets, &output, FillPattern::Zero, None)
+            .expect_err("Accepted an unaligned offset");
+        assert!(format!("{}", error).contains("offset 0x800 of \"tmp/test_bytes\""));
+
+        // the stitched output of 6Ki has to be padded to the next sector
+        output.alignment = Some(Alignment::new(4096, Some(FillPattern::One)).expect("Failed to create alignment"));
+        let offsets = vec![ByteOffset::new(0,Magnitude::Unit), ByteOffset::new(4,Magnitude::Ki)];
+        super::stitch_files(files, offsets, &output, FillPattern::Zero, None).expect("Failed to stitch two files");
+        let bytes = read_file(stitched.path()).expect("Failed to read stitched file");
+        assert_eq!(bytes.len(), 8192);
+        assert_eq!(bytes[6144..], [0xFF; 2048][..]);
+    }
 }
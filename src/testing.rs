@@ -0,0 +1,40 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File written by a test, removed when the guard is dropped so a failing assertion leaves nothing behind
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// guard for `path`, which the test creates, a file left over from an aborted run is removed first
+    pub fn new<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let _ = fs::remove_file(&path);
+        TempFile { path }
+    }
+
+    /// copy `source` to `path` and guard the copy
+    pub fn copy<P, Q>(source: P, path: Q) -> Self
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let file = Self::new(path);
+        fs::copy(source, &file.path).expect("Failed to copy test file");
+        file
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
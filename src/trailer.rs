@@ -0,0 +1,383 @@
+use bytes::Bytes;
+use std::fs;
+use std::path::Path;
+
+use errors::*;
+use signer::{Algorithm, Signer, Verifier};
+use stitch::read_file;
+
+/// Signature trailers are appended as magic, version, algorithm, fingerprint, signed length (u64 LE),
+/// signature and finally the length of the whole trailer (u32 LE), so it can be found from the end.
+/// The signature covers the signed bytes followed by the fields up to the signed length.
+const MAGIC: &[u8] = b"SCPL";
+const VERSION: u8 = 1;
+const FINGERPRINT_LEN: usize = 32;
+const HEADER_LEN: usize = 4 + 1 + 1 + FINGERPRINT_LEN + 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trailer {
+    pub algorithm: Algorithm,
+    /// SHA-256 of the DER encoded public key
+    pub fingerprint: Vec<u8>,
+    pub signed_len: u64,
+    pub signature: Vec<u8>,
+}
+
+impl Trailer {
+    fn header(algorithm: Algorithm, fingerprint: &[u8], signed_len: u64) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.push(algorithm.id());
+        header.extend_from_slice(fingerprint);
+        header.extend_from_slice(&signed_len.to_le_bytes());
+        header
+    }
+
+    /// the bytes covered by the signature
+    fn message(data: &[u8], header: &[u8]) -> Bytes {
+        let mut message = data.to_vec();
+        message.extend_from_slice(header);
+        Bytes::from(message)
+    }
+
+    /// sign `data` and create the trailer to append to it
+    pub fn sign(signer: &Signer, data: &[u8]) -> Result<Self> {
+        let verifier = signer.verifier()?;
+        let header = Self::header(verifier.algorithm(), &verifier.fingerprint(), data.len() as u64);
+        let signature = signer.calculate_signature(&Self::message(data, &header))?;
+        Ok(Self {
+            algorithm: verifier.algorithm(),
+            fingerprint: verifier.fingerprint(),
+            signed_len: data.len() as u64,
+            signature,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Self::header(self.algorithm, &self.fingerprint, self.signed_len);
+        bytes.extend_from_slice(&self.signature);
+        let len = (bytes.len() + 4) as u32;
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes
+    }
+
+    /// split the last trailer off `content`, `None` if it does not end with a trailer
+    fn split_last(content: &[u8]) -> Result<Option<(&[u8], Self)>> {
+        if content.len() < 4 {
+            return Ok(None);
+        }
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&content[content.len() - 4..]);
+        let len = u32::from_le_bytes(len) as usize;
+        if len < HEADER_LEN + 4 || len > content.len() {
+            return Ok(None);
+        }
+
+        let (data, trailer) = content.split_at(content.len() - len);
+        if !trailer.starts_with(MAGIC) {
+            return Ok(None);
+        }
+
+        let malformed = |r: String| -> Error { ScalpelError::TrailerError { r }.into() };
+        if trailer[4] != VERSION {
+            return Err(malformed(format!("unsupported version {}", trailer[4])));
+        }
+        let algorithm = Algorithm::from_id(trailer[5])
+            .ok_or_else(|| malformed(format!("unknown algorithm {}", trailer[5])))?;
+        let fingerprint = trailer[6..6 + FINGERPRINT_LEN].to_vec();
+        let mut signed_len = [0u8; 8];
+        signed_len.copy_from_slice(&trailer[6 + FINGERPRINT_LEN..HEADER_LEN]);
+
+        Ok(Some((
+            data,
+            Self {
+                algorithm,
+                fingerprint,
+                signed_len: u64::from_le_bytes(signed_len),
+                signature: trailer[HEADER_LEN..len - 4].to_vec(),
+            },
+        )))
+    }
+
+    /// split `content` into the signed bytes and its trailers in the order they were appended,
+    /// `None` if it does not end with a trailer
+    ///
+    /// Every trailer signs the same bytes, so further signatures can be appended to a signed file.
+    pub fn parse(content: &[u8]) -> Result<Option<(&[u8], Vec<Self>)>> {
+        let mut data = content;
+        let mut trailers = Vec::new();
+        while let Some((rest, trailer)) = Self::split_last(data)? {
+            data = rest;
+            trailers.insert(0, trailer);
+        }
+        if trailers.is_empty() {
+            return Ok(None);
+        }
+
+        if let Some(trailer) = trailers.iter().find(|trailer| trailer.signed_len != data.len() as u64) {
+            return Err(ScalpelError::TrailerError {
+                r: format!(
+                    "signed length {} does not match the {} bytes in front of the trailers",
+                    trailer.signed_len,
+                    data.len()
+                ),
+            }
+            .into());
+        }
+        Ok(Some((data, trailers)))
+    }
+
+    /// verify the trailer with the key of its fingerprint from `keyring`
+    pub fn verify(&self, keyring: &Keyring, data: &[u8]) -> Result<()> {
+        let verifier = keyring
+            .find(&self.fingerprint)
+            .ok_or_else(|| ScalpelError::UnknownKeyError {
+                fingerprint: to_hex(&self.fingerprint),
+            })?
+            .clone()
+            .with_algorithm(self.algorithm)?;
+        let header = Self::header(self.algorithm, &self.fingerprint, self.signed_len);
+        verifier.verify(&Self::message(data, &header), &self.signature)
+    }
+}
+
+/// Public keys to verify signature trailers with, selected by their fingerprint
+#[derive(Debug, Clone, Default)]
+pub struct Keyring {
+    verifiers: Vec<Verifier>,
+}
+
+impl Keyring {
+    /// all public keys within `dir`, files which are no public keys are skipped
+    pub fn from_dir(dir: &Path) -> Result<Self> {
+        let mut paths = fs::read_dir(dir)
+            .map_err(|err| ScalpelError::OpeningError.context(format!("{}: {:?}", err, dir)))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<::std::io::Result<Vec<_>>>()
+            .map_err(|err| ScalpelError::ReadingError.context(err))?;
+        paths.sort();
+
+        let verifiers = paths
+            .iter()
+            .filter(|path| path.is_file())
+            .filter_map(|path| match Verifier::from_file(path) {
+                Ok(verifier) => Some(verifier),
+                Err(e) => {
+                    warn!("Skipping {:?} in keyring: {}", path, e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if verifiers.is_empty() {
+            return Err(ScalpelError::KeyError {
+                r: format!("no public keys in keyring {:?}", dir),
+            }
+            .into());
+        }
+        Ok(Self { verifiers })
+    }
+
+    pub fn find(&self, fingerprint: &[u8]) -> Option<&Verifier> {
+        self.verifiers
+            .iter()
+            .find(|verifier| verifier.fingerprint() == fingerprint)
+    }
+
+    /// verify the trailers of a file, at least `threshold` distinct keys of the keyring have to have signed it
+    ///
+    /// Files without trailer need a keyring with a single key, returns the number of valid signatures.
+    pub fn verify_file<P>(&self, path: P, threshold: usize) -> Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let content = read_file(path)?;
+        match Trailer::parse(&content)? {
+            Some((data, trailers)) => self.verify_trailers(data, &trailers, threshold, path),
+            None if self.verifiers.len() == 1 && threshold <= 1 => {
+                self.verifiers[0].verify_file(path)?;
+                Ok(1)
+            }
+            None => Err(no_trailer(path)),
+        }
+    }
+
+    /// verify the trailers or the plain signature of `content` read from `path` like `verify_file`,
+    /// returns the signed data without the signatures
+    pub fn verify<'a>(&self, content: &'a [u8], threshold: usize, path: &Path) -> Result<&'a [u8]> {
+        match Trailer::parse(content)? {
+            Some((data, trailers)) => {
+                self.verify_trailers(data, &trailers, threshold, path)?;
+                Ok(data)
+            }
+            None if self.verifiers.len() == 1 && threshold <= 1 => self.verifiers[0].verify_signed(content),
+            None => Err(no_trailer(path)),
+        }
+    }
+
+    fn verify_trailers(&self, data: &[u8], trailers: &[Trailer], threshold: usize, path: &Path) -> Result<usize> {
+        let mut valid: Vec<&[u8]> = Vec::new();
+        for trailer in trailers.iter() {
+            match trailer.verify(self, data) {
+                Ok(()) if !valid.contains(&&trailer.fingerprint[..]) => valid.push(&trailer.fingerprint),
+                Ok(()) => warn!("{:?}: key {} signed more than once", path, to_hex(&trailer.fingerprint)),
+                Err(e) => warn!("{:?}: signature of key {} is not valid: {}", path, to_hex(&trailer.fingerprint), e),
+            }
+        }
+
+        if valid.len() < threshold.max(1) {
+            return Err(ScalpelError::ThresholdError {
+                valid: valid.len(),
+                threshold: threshold.max(1),
+                total: trailers.len(),
+            }
+            .into());
+        }
+        Ok(valid.len())
+    }
+}
+
+fn no_trailer(path: &Path) -> Error {
+    ScalpelError::TrailerError {
+        r: format!("no signature trailer in {:?}", path),
+    }
+    .into()
+}
+
+impl<'a> From<&'a [Signer]> for Keyring {
+    fn from(signers: &'a [Signer]) -> Self {
+        Self {
+            verifiers: signers.iter().filter_map(|signer| signer.verifier().ok()).collect(),
+        }
+    }
+}
+
+/// sign the file at `path` with each of `signers`, returns the trailers to append to it
+///
+/// Trailers of a signed file are kept and the new signatures cover the same bytes.
+pub fn sign_file(signers: &[Signer], path: &Path) -> Result<Vec<u8>> {
+    let content = read_file(path)?;
+    let data = match Trailer::parse(&content)? {
+        Some((data, _)) => data,
+        None => &content[..],
+    };
+
+    let mut trailers = Vec::new();
+    for signer in signers {
+        trailers.extend(Trailer::sign(signer, data)?.to_bytes());
+    }
+    Ok(trailers)
+}
+
+impl From<Verifier> for Keyring {
+    fn from(verifier: Verifier) -> Self {
+        Self {
+            verifiers: vec![verifier],
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::TempFile;
+
+    #[test]
+    fn trailer_roundtrip() {
+        let keyring = Keyring::from_dir(Path::new("tmp/keyring")).expect("Failed to read keyring");
+        let data = vec![0x42u8; 1000];
+
+        for key in &["tmp/ed25519_keypair.pem", "tmp/rsa2048_keypair.pk8", "tmp/p256_keypair.pk8"] {
+            let signer = if key.ends_with(".pem") {
+                Signer::from_pem_file(Path::new(key))
+            } else {
+                Signer::from_pkcs8_file(Path::new(key))
+            }
+            .expect("Failed to read keys");
+            let trailer = Trailer::sign(&signer, &data).expect("Signing failed");
+
+            let mut signed = data.clone();
+            signed.extend_from_slice(&trailer.to_bytes());
+            let (parsed_data, parsed) = Trailer::parse(&signed)
+                .expect("Failed to parse trailer")
+                .expect("No trailer found");
+            assert_eq!(parsed_data, &data[..]);
+            assert_eq!(parsed, vec![trailer.clone()]);
+            assert!(parsed[0].verify(&keyring, parsed_data).is_ok());
+
+            let mut tampered = signed.clone();
+            tampered[10] ^= 1;
+            let (tampered_data, tampered_trailers) = Trailer::parse(&tampered).unwrap().unwrap();
+            assert!(tampered_trailers[0].verify(&keyring, tampered_data).is_err());
+            // the algorithm is covered by the signature as well
+            if trailer.algorithm == Algorithm::RsaPkcs1Sha256 {
+                signed[data.len() + 5] = Algorithm::RsaPssSha256.id();
+                let (data, trailers) = Trailer::parse(&signed).unwrap().unwrap();
+                assert!(trailers[0].verify(&keyring, data).is_err());
+            }
+        }
+
+        // unknown keys are reported by fingerprint
+        let trailer = Trailer::sign(&Signer::random(), &data).expect("Signing failed");
+        match trailer.verify(&keyring, &data) {
+            Err(e) => assert!(format!("{}", e).starts_with("No public key with fingerprint")),
+            Ok(_) => panic!("Verified with an unknown key"),
+        }
+    }
+
+    #[test]
+    fn no_trailer() {
+        assert!(Trailer::parse(&[]).unwrap().is_none());
+        assert!(Trailer::parse(&[0x42u8; 100]).unwrap().is_none());
+
+        let signer = Signer::random();
+        let mut signed = vec![0x42u8; 100];
+        signed.extend_from_slice(&Trailer::sign(&signer, &[0x42u8; 100]).unwrap().to_bytes());
+        // signed length does not match
+        let mut shifted = vec![0u8];
+        shifted.extend_from_slice(&signed);
+        assert!(Trailer::parse(&shifted).is_err());
+        // unsupported version
+        signed[104] = 2;
+        assert!(Trailer::parse(&signed).is_err());
+    }
+
+    #[test]
+    fn countersign() {
+        let image = TempFile::copy("tmp/test_bytes", "tmp/test_countersign.bin");
+        let path = image.path();
+        let keyring = Keyring::from_dir(Path::new("tmp/keyring")).expect("Failed to read keyring");
+        let engineering = Signer::from_pem_file(Path::new("tmp/ed25519_keypair.pem")).expect("Failed to read keys");
+        let release = Signer::from_pkcs8_file(Path::new("tmp/p256_keypair.pk8")).expect("Failed to read keys");
+        let append = |trailers: Vec<u8>| {
+            let mut file = ::std::fs::OpenOptions::new().append(true).open(path).expect("Failed to open file");
+            ::std::io::Write::write_all(&mut file, &trailers).expect("Failed to append trailers");
+        };
+
+        append(sign_file(&[engineering], path).expect("Signing failed"));
+        assert_eq!(keyring.verify_file(path, 1).unwrap(), 1);
+        assert!(keyring.verify_file(path, 2).is_err());
+
+        // countersign the signed file, an unknown key does not count
+        append(sign_file(&[release, Signer::random()], path).expect("Signing failed"));
+        let content = read_file(path).expect("Failed to read file");
+        let (data, trailers) = Trailer::parse(&content).unwrap().unwrap();
+        assert_eq!(data.len(), 2048);
+        assert_eq!(trailers.len(), 3);
+        assert_eq!(keyring.verify_file(path, 2).unwrap(), 2);
+        match keyring.verify_file(path, 3) {
+            Err(e) => assert_eq!(format!("{}", e), "Only 2 of 3 required keys signed validly, out of 3 signatures"),
+            Ok(_) => panic!("Accepted too few signatures"),
+        }
+
+        // the same key signing twice counts once
+        let engineering = Signer::from_pem_file(Path::new("tmp/ed25519_keypair.pem")).expect("Failed to read keys");
+        append(sign_file(&[engineering], path).expect("Signing failed"));
+        assert_eq!(keyring.verify_file(path, 2).unwrap(), 2);
+    }
+}